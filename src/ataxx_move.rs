@@ -2,7 +2,7 @@ use crate::types::*;
 use crate::utils::*;
 use std::fmt;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct AtaxxMove {
     pub from: Square,
     pub to: Square
@@ -47,6 +47,24 @@ impl AtaxxMove
         }
     }
 
+    // Like from_uai, but returns None instead of panicking if uai_move isn't a move
+    pub fn try_from_uai(uai_move: &str) -> Option<AtaxxMove>
+    {
+        let is_square = |sq: &str| {
+            let bytes = sq.as_bytes();
+            bytes.len() == 2 && (b'a'..=b'g').contains(&bytes[0]) && (b'1'..=b'7').contains(&bytes[1])
+        };
+
+        if uai_move == "0000" 
+        || (uai_move.len() == 2 && is_square(uai_move))
+        || (uai_move.len() == 4 && uai_move.is_ascii() && is_square(&uai_move[0..2]) && is_square(&uai_move[2..4]))
+        {
+            return Some(Self::from_uai(uai_move));
+        }
+
+        None
+    }
+
     // encoded has from to squares in the lowest 12 bits
     pub fn from_u12(encoded: u16) -> Self {
         Self {
@@ -1,6 +1,7 @@
 use crate::types::*;
 use crate::board::*;
 use crate::search::*;
+use crate::time_manager::*;
 use crate::uai::*;
 
 pub const DEFAULT_BENCH_DEPTH: u8 = 16;
@@ -26,11 +27,14 @@ const FENS: [&str; 18] = [
     "1o1o1o1/2o4/7/1x5/2x4/7/o6 x 0 5"
 ];
 
-pub fn bench(depth: u8) {
+// Uses a fresh searcher so results are reproducible, except for the Quiescence option, for A/B testing
+pub fn bench(depth: u8, qsearch_enabled: bool) {
     println!("Running bench depth {}", depth);
 
     let mut searcher = Searcher::new(Board::new(START_FEN));
+    searcher.set_qsearch(qsearch_enabled);
     let mut nodes: u64 = 0;
+    let mut qs_nodes: u64 = 0;
     let mut milliseconds: u64 = 0;
 
     for &fen in FENS.iter() 
@@ -38,13 +42,14 @@ pub fn bench(depth: u8) {
         searcher.board = Board::new(fen);
         assert!(searcher.board.game_state().0 == GameState::Ongoing);
 
-        searcher.search(depth, I64_MAX, 0, true, U64_MAX, U64_MAX, false);
+        searcher.search(depth, TimeManager::infinite(), U64_MAX, U64_MAX, false);
         milliseconds += searcher.milliseconds_elapsed();
         nodes += searcher.get_nodes();
+        qs_nodes += searcher.get_qs_nodes();
 
         uainewgame(&mut searcher);
     }
 
-    println!("bench depth {} nodes {} nps {} time {}", 
-        depth, nodes, nodes * 1000 / milliseconds, milliseconds);
+    println!("bench depth {} nodes {} nps {} time {} qsnodes {}", 
+        depth, nodes, nodes * 1000 / milliseconds, milliseconds, qs_nodes);
 }
\ No newline at end of file
@@ -1,7 +1,39 @@
 use crate::types::*;
 use crate::utils::*;
 use crate::ataxx_move::*;
+use crate::nn::*;
 use arrayvec::ArrayVec;
+use std::fmt;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum FenError {
+    MissingField(&'static str),
+    TooManyFields(usize),
+    SideToMove(String),
+    HalfmoveClock(String),
+    FullmoveCounter(String),
+    RowCount(usize),
+    RowLength { rank: usize, length: usize }, // length in squares, rank from 1 to 7
+    UnknownChar(char),
+}
+
+impl fmt::Display for FenError 
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {}", field),
+            FenError::TooManyFields(num_fields) => write!(f, "expected at most 4 fields but got {}", num_fields),
+            FenError::SideToMove(stm) => write!(f, "invalid side to move {}, expected x, o, r or b", stm),
+            FenError::HalfmoveClock(halfmove) => write!(f, "invalid halfmove clock {}", halfmove),
+            FenError::FullmoveCounter(fullmove) => write!(f, "invalid fullmove counter {}", fullmove),
+            FenError::RowCount(num_rows) => write!(f, "expected 7 rows but got {}", num_rows),
+            FenError::RowLength { rank, length } => 
+                write!(f, "row of rank {} has {} squares, expected 7", rank, length),
+            FenError::UnknownChar(ch) => write!(f, "unknown character {}", ch),
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub struct BoardState
@@ -29,50 +61,87 @@ impl BoardState
         }
     }
     
-    pub fn new(fen: &str) -> Self {
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
         // Fen: pieces stm halfmove fullmove 
         // r5b/7/7/7/7/7/b5r r 0 1
         // r5b/7/2-1-2/7/2-1-2/7/b5r r 0 1
+        // The halfmove and fullmove counters are optional
 
         let mut board_state: BoardState = Self::default();
 
-        let fen = fen.trim().to_string();
-        let fen_split: Vec<&str> = fen.split(' ').map(str::trim).collect();
-        let fen_rows: Vec<&str> = fen_split[0].split('/').map(str::trim).collect();
+        let fen_split: Vec<&str> = fen.split_whitespace().collect();
+
+        if fen_split.is_empty() {
+            return Err(FenError::MissingField("pieces"));
+        }
+        if fen_split.len() < 2 {
+            return Err(FenError::MissingField("side to move"));
+        }
+        if fen_split.len() > 4 {
+            return Err(FenError::TooManyFields(fen_split.len()));
+        }
 
-        board_state.color = if fen_split[1] == "r" || fen_split[1] == "x" {Color::Red} else {Color::Blue};
+        board_state.color = match fen_split[1] {
+            "r" | "x" => Color::Red,
+            "b" | "o" => Color::Blue,
+            _ => return Err(FenError::SideToMove(fen_split[1].to_string()))
+        };
         board_state.zobrist_hash ^= ZOBRIST_COLOR[board_state.color as usize];
 
-        board_state.plies_since_single = fen_split[2].parse().unwrap();
-        board_state.move_counter = fen_split[3].parse().unwrap();
+        if let Some(halfmove) = fen_split.get(2) {
+            board_state.plies_since_single = halfmove.parse()
+                .map_err(|_| FenError::HalfmoveClock(halfmove.to_string()))?;
+        }
+        else {
+            board_state.plies_since_single = 0;
+        }
+
+        if let Some(fullmove) = fen_split.get(3) {
+            board_state.move_counter = fullmove.parse()
+                .map_err(|_| FenError::FullmoveCounter(fullmove.to_string()))?;
+        }
 
         // Parse fen rows/pieces
-        let mut rank: i16 = 6;
-        let mut file: i16 = 0;
-        for fen_row in &fen_rows {
-            for my_char in fen_row.chars() {
-                let sq = rank * 7 + file;
-                if my_char == 'r' || my_char == 'x' {
-                    board_state.place_piece(Color::Red, sq as Square);
-                }
-                else if my_char == 'b' || my_char == 'o' {
-                    board_state.place_piece(Color::Blue, sq as Square);
-                }
-                else if my_char == '-' {
-                    board_state.gaps |= 1u64 << sq;
+        let fen_rows: Vec<&str> = fen_split[0].split('/').collect();
+        if fen_rows.len() != 7 {
+            return Err(FenError::RowCount(fen_rows.len()));
+        }
+
+        for (row_idx, fen_row) in fen_rows.iter().enumerate() 
+        {
+            let rank: usize = 6 - row_idx;
+            let mut file: usize = 0;
+
+            for my_char in fen_row.chars() 
+            {
+                // Squares past the end of the row would overlap the next rank
+                let num_squares: usize = match my_char {
+                    '1'..='7' => char_to_digit(my_char) as usize,
+                    'r' | 'x' | 'b' | 'o' | '-' => 1,
+                    _ => return Err(FenError::UnknownChar(my_char))
+                };
+
+                if file + num_squares > 7 {
+                    return Err(FenError::RowLength { rank: rank + 1, length: file + num_squares });
                 }
-                else
-                {
-                    let empty_squares = char_to_digit(my_char);
-                    file += (empty_squares - 1) as i16;
+
+                let sq = (rank * 7 + file) as Square;
+                match my_char {
+                    'r' | 'x' => board_state.place_piece(Color::Red, sq),
+                    'b' | 'o' => board_state.place_piece(Color::Blue, sq),
+                    '-' => board_state.gaps |= 1u64 << sq,
+                    _ => { }
                 }
-                file += 1;
+
+                file += num_squares;
+            }
+
+            if file != 7 {
+                return Err(FenError::RowLength { rank: rank + 1, length: file });
             }
-            rank -= 1;
-            file = 0;
         }
 
-        board_state
+        Ok(board_state)
     }
 
     pub fn place_piece(&mut self, color: Color, sq: Square)
@@ -308,20 +377,29 @@ pub struct Board
 {
     state: *mut BoardState,
     states: Vec<BoardState>,
+    accumulators: Vec<Accumulator>, // [state index], lazily updated when evaluating
 }
 
 impl Board
 {
+    // Panics if the fen is invalid, use from_fen for fens that aren't known to be valid
     pub fn new(fen: &str) -> Self
+    {
+        Self::from_fen(fen).unwrap_or_else(|error| panic!("Invalid fen '{}': {}", fen, error))
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, FenError>
     {
         let mut board: Board = Self {
             state: std::ptr::null_mut(),
             states: Vec::with_capacity(256),
+            accumulators: Vec::with_capacity(256),
         };
 
-        board.states.push(BoardState::new(fen));
+        board.states.push(BoardState::from_fen(fen)?);
+        board.accumulators.push(Accumulator::default());
         board.state = board.states.last_mut().unwrap() as *mut _;
-        board
+        Ok(board)
     }
 
     pub fn num_states(&self) -> usize {
@@ -339,7 +417,12 @@ impl Board
     pub fn blue(&self) -> u64 {
         unsafe { (*self.state).bitboards[Color::Blue as usize] }
     }
+
+    pub fn gaps(&self) -> u64 {
+        unsafe { (*self.state).gaps }
+    }
     
+    #[allow(dead_code)]
     pub fn us(&self) -> u64 { 
         unsafe { (*self.state).bitboards[self.side_to_move() as usize] }
     }
@@ -365,14 +448,21 @@ impl Board
         unsafe { (*self.state).plies_since_single }
     }
 
+    // Fullmove counter
+    pub fn move_counter(&self) -> u16 {
+        unsafe { (*self.state).move_counter }
+    }
+
     pub fn place_piece(&mut self, color: Color, sq: Square)
     {
         unsafe { (*self.state).place_piece(color, sq); }
+        self.accumulators[self.states.len() - 1].updated = false;
     }
 
     pub fn remove_piece(&mut self, color: Color, sq: Square)
     {
         unsafe { (*self.state).remove_piece(color, sq); }
+        self.accumulators[self.states.len() - 1].updated = false;
     }
 
     #[allow(dead_code)]
@@ -405,10 +495,39 @@ impl Board
         unsafe { (*self.state).moves(moves); }
     }
 
+    // Repetitions aren't checked here, see is_repetition()
     pub fn game_state(&mut self) -> (GameState, Color) {
         unsafe { (*self.state).game_state() }
     }
 
+    // True if the current position occurred in the last ply plies (i.e. inside the search tree)
+    // or twice before (threefold repetition)
+    pub fn is_repetition(&self, ply: u8) -> bool
+    {
+        let hash: u64 = self.zobrist_hash();
+        let current_idx: usize = self.states.len() - 1;
+
+        // A single move adds a piece, so positions before the last single move can't repeat
+        let max_distance: usize = (self.plies_since_single() as usize).min(current_idx);
+        let mut repetitions: u8 = 0;
+
+        // Positions with the same side to move are an even number of plies apart
+        for distance in (2..=max_distance).step_by(2)
+        {
+            if self.states[current_idx - distance].zobrist_hash != hash {
+                continue;
+            }
+
+            repetitions += 1;
+
+            if distance <= ply as usize || repetitions >= 2 {
+                return true;
+            }
+        }
+
+        false
+    }
+
     pub fn must_pass(&self) -> bool {
         unsafe { (*self.state).must_pass() }
     }
@@ -418,10 +537,48 @@ impl Board
         (ADJACENT[sq as usize] & self.them()).count_ones() as u8
     }
 
-    pub fn make_move(&mut self, mov: AtaxxMove) {
+    pub fn make_move(&mut self, mov: AtaxxMove) 
+    {
         unsafe { self.states.push(*self.state); }
         self.state = self.states.last_mut().unwrap() as *mut _;
         unsafe { (*self.state).make_move(mov); }
+
+        // The accumulators vec is never shrunk, so usually there's already one for this state
+        if self.accumulators.len() < self.states.len() {
+            self.accumulators.push(Accumulator::default());
+        }
+        self.accumulators[self.states.len() - 1].updated = false;
+    }
+
+    // Accumulator of the current position, updated from the closest ancestor with an updated one
+    pub fn accumulator(&mut self) -> &Accumulator
+    {
+        let current: usize = self.states.len() - 1;
+
+        if !self.accumulators[current].updated 
+        {
+            let mut ancestor: usize = current;
+            while ancestor > 0 && !self.accumulators[ancestor].updated {
+                ancestor -= 1;
+            }
+
+            if self.accumulators[ancestor].updated {
+                let (before, after) = self.accumulators.split_at_mut(current);
+                after[0].update(&before[ancestor], &self.states[ancestor].bitboards, &self.states[current].bitboards);
+            }
+            else {
+                self.accumulators[current].refresh(&self.states[current].bitboards);
+            }
+        }
+
+        &self.accumulators[current]
+    }
+
+    // Must be called when the net changes
+    pub fn reset_accumulators(&mut self) {
+        for accumulator in self.accumulators.iter_mut() {
+            accumulator.updated = false;
+        }
     }
 
     pub fn undo_move(&mut self)
@@ -433,11 +590,15 @@ impl Board
     }
 }
 
+// The state pointer always points into the board's own states vec, so moving the board to another thread is safe
+unsafe impl Send for Board {}
+
 impl Clone for Board {
     fn clone(&self) -> Self {
         let mut cloned = Self {
             state: std::ptr::null_mut(),
             states: self.states.clone(),
+            accumulators: self.accumulators[0..self.states.len()].to_vec(),
         };
 
         assert!(cloned.states.len() >= 1);
@@ -7,14 +7,13 @@ if not defined numThreads (
     echo Usage: src\datagen.bat ^<numThreads^>
     pause
 ) else (
-    set command=target\release\zataxx.exe datagen
     if "%2" == "datagen_openings" (
-        set command=target\release\zataxx.exe datagen_openings
-    )
-
-    for /l %%i in (1, 1, %numThreads%) do (
-        timeout /nobreak /t 1 > nul
-        start cmd.exe /k "!command!"
+        for /l %%i in (1, 1, %numThreads%) do (
+            timeout /nobreak /t 1 > nul
+            start cmd.exe /k "target\release\zataxx.exe datagen_openings"
+        )
+    ) else (
+        start cmd.exe /k "target\release\zataxx.exe datagen --threads %numThreads%"
     )
 )
 
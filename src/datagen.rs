@@ -1,8 +1,14 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use std::time::Instant;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::fs;
+use std::thread;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use crate::uai::*;
 use crate::types::*;
 use crate::utils::*;
@@ -10,22 +16,14 @@ use crate::ataxx_move::*;
 use arrayvec::ArrayVec;
 use crate::board::*;
 use crate::search::*;
+use crate::time_manager::*;
+use crate::training_data::*;
 
 pub const CHARACTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
-pub fn datagen()
-{   
-    pub const MIN_PLIES: u8 = 14;
-    pub const MAX_PLIES: u8 = 17;
-    pub const SOFT_NODES: u64 = 5000;
-    pub const HARD_NODES: u64 = 10_000;
-    pub const MAX_OPENING_SCORE: i32 = 300;
-    pub const ADJUDICATION_SCORE: i32 = 8000;
-
-    // Create 'data' folder if doesnt exist
-    let _ = fs::create_dir_all("data");
-
-    // random file name
+// data/<12 random characters>.<extension>
+fn random_file_path(extension: &str) -> String 
+{
     let file_name: String = (0..12)
     .map(|_| {
         let random_index = rand::thread_rng().gen_range(0..CHARACTERS.len());
@@ -33,25 +31,476 @@ pub fn datagen()
     })
     .collect();
 
-    let file_path = format!("data/{}.txt", file_name);
+    format!("data/{}.{}", file_name, extension)
+}
 
-    // Open the file in write mode, creating it if it doesn't exist
-    let mut file = match File::create(file_path.clone()) {
+// Calls set(name, value) for each '--name value' pair
+pub fn parse_flags(args: &[String], mut set: impl FnMut(&str, &str) -> Result<(), String>) -> Result<(), String>
+{
+    let mut i: usize = 0;
+    while i < args.len()
+    {
+        let name: &str = args[i].trim().strip_prefix("--")
+            .ok_or(format!("Expected an option starting with -- but got {}", args[i].trim()))?;
+
+        let value: &str = args.get(i + 1).map(|value| value.trim())
+            .ok_or(format!("Missing value for --{}", name))?;
+
+        set(name, value)?;
+        i += 2;
+    }
+
+    Ok(())
+}
+
+pub fn parse_flag_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid value {} for --{}", value, name))
+}
+
+// Creates the output file and its folder
+fn create_output_file(file_path: &str) -> File
+{
+    if let Some(parent) = std::path::Path::new(file_path).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+
+    match File::create(file_path) {
         Ok(file) => file,
         Err(e) => panic!("Error creating file {}: {}", file_path, e),
+    }
+}
+
+// Opens an existing output file to append to it, discarding anything after the first 'length' bytes
+fn open_resumed_output_file(file_path: &str, length: u64) -> File
+{
+    let file = match OpenOptions::new().append(true).open(file_path) {
+        Ok(file) => file,
+        Err(e) => panic!("Error opening file {}: {}", file_path, e),
     };
 
+    if let Err(e) = file.set_len(length) {
+        panic!("Error truncating file {}: {}", file_path, e);
+    }
+
+    file
+}
+
+// The RNG of each game only depends on the seed and the game's index
+fn game_rng(seed: u64, game_index: u64) -> StdRng
+{
+    let mut rng_seed = [0u8; 32];
+    rng_seed[0..8].copy_from_slice(&seed.to_le_bytes());
+    rng_seed[8..16].copy_from_slice(&game_index.to_le_bytes());
+    StdRng::from_seed(rng_seed)
+}
+
+// <output>.meta has the config and the progress of a datagen run as 'name value' lines
+pub fn metadata_path(output: &str) -> String {
+    format!("{}.meta", output)
+}
+
+fn read_metadata(output: &str) -> Result<Vec<(String, String)>, String>
+{
+    let file_path: String = metadata_path(output);
+    let contents: String = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Error reading {}: {}", file_path, e))?;
+
+    contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.trim().split_once(' ') {
+            Some((name, value)) => Ok((name.to_string(), value.trim().to_string())),
+            None => Err(format!("Invalid line in {}: {}", file_path, line))
+        })
+        .collect()
+}
+
+// How far a datagen run got, saved after every game written
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct DatagenProgress {
+    pub next_game: u64, // index of the next game to write, including skipped games
+    pub games: u64,
+    pub positions: u64,
+    pub bytes: u64, // length of the output file
+}
+
+impl DatagenProgress
+{
+    pub const KEYS: [&'static str; 4] = ["next-game", "games-written", "positions-written", "bytes-written"];
+
+    fn from_metadata(metadata: &[(String, String)]) -> Result<Self, String>
+    {
+        let value = |key: &str| -> Result<u64, String> {
+            let (name, value) = metadata.iter().find(|(name, _)| name == key)
+                .ok_or(format!("Missing {} in metadata", key))?;
+            parse_flag_value(name, value)
+        };
+
+        Ok(Self {
+            next_game: value(Self::KEYS[0])?,
+            games: value(Self::KEYS[1])?,
+            positions: value(Self::KEYS[2])?,
+            bytes: value(Self::KEYS[3])?
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct DatagenConfig {
+    pub min_plies: u8, // random opening plies
+    pub max_plies: u8,
+    pub soft_nodes: u64,
+    pub hard_nodes: u64,
+    pub max_opening_score: i32, // skip more unbalanced openings
+    pub adjudication_score: i32,
+    pub threads: usize,
+    pub format: DataFormat,
+    pub output: String,
+    pub max_games: Option<u64>, // stop after this many games
+    pub max_positions: Option<u64>, // stop after this many positions
+    pub seed: u64,
+    pub resume: Option<DatagenProgress>, // progress of the run to continue
+}
+
+impl Default for DatagenConfig 
+{
+    fn default() -> Self {
+        Self {
+            min_plies: 14,
+            max_plies: 17,
+            soft_nodes: 5000,
+            hard_nodes: 10_000,
+            max_opening_score: 300,
+            adjudication_score: 8000,
+            threads: 1,
+            format: DataFormat::Text,
+            output: random_file_path(DataFormat::Text.extension()),
+            max_games: None,
+            max_positions: None,
+            seed: rand::thread_rng().gen(),
+            resume: None
+        }
+    }
+}
+
+impl DatagenConfig
+{
+    pub const USAGE: &'static str = "Usage: zataxx datagen [--min-plies N] [--max-plies N] [--soft-nodes N] \
+[--hard-nodes N] [--max-opening-score N] [--adjudication-score N] [--threads N] [--format text|binary] [--output PATH] [--games N] [--positions N] [--seed N] [--resume PATH]";
+
+    pub fn from_args(args: &[String]) -> Result<Self, String>
+    {
+        // --resume <output> continues a run with its saved options. Only the options that don't change
+        // the data (--threads) or that only change where it ends (--games, --positions) can be given with it.
+        if let Some(i) = args.iter().position(|arg| arg.trim() == "--resume") 
+        {
+            let output: String = args.get(i + 1).map(|arg| arg.trim().to_string())
+                .ok_or("Missing value for --resume")?;
+
+            let other_args: Vec<String> = args[..i].iter().chain(args[i + 2..].iter()).cloned().collect();
+            parse_flags(&other_args, |name, _| {
+                if ["threads", "games", "positions"].contains(&name) {
+                    Ok(())
+                } else {
+                    Err(format!("--{} can't be given with --resume, only --threads, --games and --positions can", name))
+                }
+            })?;
+
+            let metadata: Vec<(String, String)> = read_metadata(&output)?;
+            let progress = DatagenProgress::from_metadata(&metadata)?;
+
+            let output_length: u64 = fs::metadata(&output).map(|metadata| metadata.len()).unwrap_or(0);
+            if output_length < progress.bytes {
+                return Err(format!("{} has {} bytes but {} were written", output, output_length, progress.bytes));
+            }
+
+            let mut resumed_args: Vec<String> = Vec::new();
+            for (name, value) in metadata.into_iter().filter(|(name, _)| !DatagenProgress::KEYS.contains(&name.as_str())) {
+                resumed_args.extend([format!("--{}", name), value]);
+            }
+
+            resumed_args.extend(other_args);
+            resumed_args.extend(["--output".to_string(), output]);
+
+            let mut config = Self::from_args(&resumed_args)?;
+            config.resume = Some(progress);
+            return Ok(config);
+        }
+
+        let mut config = Self::default();
+        let mut output: Option<String> = None;
+        let mut format: Option<DataFormat> = None;
+
+        parse_flags(args, |name, value| {
+            match name {
+                "min-plies" => config.min_plies = parse_flag_value(name, value)?,
+                "max-plies" => config.max_plies = parse_flag_value(name, value)?,
+                "soft-nodes" => config.soft_nodes = parse_flag_value(name, value)?,
+                "hard-nodes" => config.hard_nodes = parse_flag_value(name, value)?,
+                "max-opening-score" => config.max_opening_score = parse_flag_value(name, value)?,
+                "adjudication-score" => config.adjudication_score = parse_flag_value(name, value)?,
+                "threads" => config.threads = parse_flag_value(name, value)?,
+                "format" => format = match value {
+                    "text" => Some(DataFormat::Text),
+                    "binary" => Some(DataFormat::Binary),
+                    _ => return Err(format!("Invalid value {} for --format, expected text or binary", value))
+                },
+                "output" => output = Some(value.to_string()),
+                "games" => config.max_games = Some(parse_flag_value(name, value)?),
+                "positions" => config.max_positions = Some(parse_flag_value(name, value)?),
+                "seed" => config.seed = parse_flag_value(name, value)?,
+                _ => return Err(format!("Unknown option --{}", name))
+            }
+            Ok(())
+        })?;
+
+        // Without --format, the format is given by the output extension
+        config.format = format.unwrap_or(output.as_deref().map_or(DataFormat::Text, DataFormat::from_path));
+        config.output = output.unwrap_or(random_file_path(config.format.extension()));
+
+        if config.threads == 0 {
+            return Err("--threads must be at least 1".to_string());
+        }
+
+        if config.min_plies > config.max_plies {
+            return Err(format!("--min-plies {} is greater than --max-plies {}", config.min_plies, config.max_plies));
+        }
+
+        if config.soft_nodes > config.hard_nodes {
+            return Err(format!("--soft-nodes {} is greater than --hard-nodes {}", config.soft_nodes, config.hard_nodes));
+        }
+
+        Ok(config)
+    }
+
+    pub fn print(&self)
+    {
+        let limit_to_string = |limit: Option<u64>| limit.map_or("none".to_string(), |limit| limit.to_string());
+
+        println!("Random opening plies: {} to {}", self.min_plies, self.max_plies);
+        println!("Soft nodes: {}", self.soft_nodes);
+        println!("Hard nodes: {}", self.hard_nodes);
+        println!("Max opening score: {}", self.max_opening_score);
+        println!("Adjudication score: {}", self.adjudication_score);
+        println!("Threads: {}", self.threads);
+        println!("Format: {:?}", self.format);
+        println!("Output: {}", self.output);
+        println!("Games limit: {}", limit_to_string(self.max_games));
+        println!("Positions limit: {}", limit_to_string(self.max_positions));
+        println!("Seed: {}", self.seed);
+    }
+
+    // The options that reproduce this run, and its progress
+    pub fn metadata(&self, progress: &DatagenProgress) -> String
+    {
+        let mut metadata: String = format!("min-plies {}\nmax-plies {}\nsoft-nodes {}\nhard-nodes {}\n\
+max-opening-score {}\nadjudication-score {}\nthreads {}\nformat {}\nseed {}\n",
+            self.min_plies, self.max_plies, self.soft_nodes, self.hard_nodes, self.max_opening_score,
+            self.adjudication_score, self.threads, if self.format == DataFormat::Binary { "binary" } else { "text" }, self.seed);
+
+        if let Some(max_games) = self.max_games {
+            metadata += &format!("games {}\n", max_games);
+        }
+        if let Some(max_positions) = self.max_positions {
+            metadata += &format!("positions {}\n", max_positions);
+        }
+
+        let values: [u64; 4] = [progress.next_game, progress.games, progress.positions, progress.bytes];
+        for (key, value) in DatagenProgress::KEYS.iter().zip(values) {
+            metadata += &format!("{} {}\n", key, value);
+        }
+
+        metadata
+    }
+
+    // Written to a temporary file first, so being killed never leaves a partial metadata file
+    fn write_metadata(&self, progress: &DatagenProgress)
+    {
+        let file_path: String = metadata_path(&self.output);
+        let temp_file_path: String = file_path.clone() + ".tmp";
+
+        if let Err(e) = fs::write(&temp_file_path, self.metadata(progress)).and_then(|_| fs::rename(&temp_file_path, &file_path)) {
+            panic!("Error writing {}: {}", file_path, e);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OpeningsConfig {
+    pub plies: usize,
+    pub soft_nodes: u64,
+    pub hard_nodes: u64,
+    pub max_opening_score: i32,
+    pub output: String,
+    pub max_openings: Option<u64>, // stop after this many openings
+}
+
+impl Default for OpeningsConfig 
+{
+    fn default() -> Self {
+        Self {
+            plies: 8,
+            soft_nodes: 500_000,
+            hard_nodes: 5_000_000,
+            max_opening_score: 5,
+            output: random_file_path("txt"),
+            max_openings: None
+        }
+    }
+}
+
+impl OpeningsConfig
+{
+    pub const USAGE: &'static str = "Usage: zataxx datagen_openings [--plies N] [--soft-nodes N] [--hard-nodes N] \
+[--max-opening-score N] [--output PATH] [--openings N]";
+
+    pub fn from_args(args: &[String]) -> Result<Self, String>
+    {
+        let mut config = Self::default();
+
+        parse_flags(args, |name, value| {
+            match name {
+                "plies" => config.plies = parse_flag_value(name, value)?,
+                "soft-nodes" => config.soft_nodes = parse_flag_value(name, value)?,
+                "hard-nodes" => config.hard_nodes = parse_flag_value(name, value)?,
+                "max-opening-score" => config.max_opening_score = parse_flag_value(name, value)?,
+                "output" => config.output = value.to_string(),
+                "openings" => config.max_openings = Some(parse_flag_value(name, value)?),
+                _ => return Err(format!("Unknown option --{}", name))
+            }
+            Ok(())
+        })?;
+
+        if config.plies == 0 {
+            return Err("--plies must be at least 1".to_string());
+        }
+
+        if config.soft_nodes > config.hard_nodes {
+            return Err(format!("--soft-nodes {} is greater than --hard-nodes {}", config.soft_nodes, config.hard_nodes));
+        }
+
+        Ok(config)
+    }
+
+    pub fn print(&self)
+    {
+        println!("Opening plies: {}", self.plies);
+        println!("Soft nodes: {}", self.soft_nodes);
+        println!("Hard nodes: {}", self.hard_nodes);
+        println!("Max opening score: {}", self.max_opening_score);
+        println!("Output: {}", self.output);
+        println!("Openings limit: {}", self.max_openings.map_or("none".to_string(), |limit| limit.to_string()));
+    }
+}
+
+pub fn datagen(config: &DatagenConfig)
+{   
+    config.print();
+
+    let file_path: &str = &config.output;
+    let mut progress: DatagenProgress = config.resume.unwrap_or_default();
+
+    let file: File = if config.resume.is_some() {
+        println!("Resuming from game {} with {} games and {} positions written", 
+            progress.next_game, progress.games, progress.positions);
+        open_resumed_output_file(file_path, progress.bytes)
+    } else {
+        create_output_file(file_path)
+    };
+
+    let mut writer = DataWriter::new(BufWriter::new(file), config.format);
+    config.write_metadata(&progress);
+
+    let limit_reached = |progress: &DatagenProgress| -> bool {
+        config.max_games.is_some_and(|max_games| progress.games >= max_games)
+        || config.max_positions.is_some_and(|max_positions| progress.positions >= max_positions)
+    };
+
+    let next_game = AtomicU64::new(progress.next_game);
+    let stop = AtomicBool::new(limit_reached(&progress));
+    let (sender, receiver) = mpsc::channel::<(u64, Vec<DataEntry>)>();
+    let mut finished_games: BTreeMap<u64, Vec<DataEntry>> = BTreeMap::new();
+    let (mut positions_this_run, datagen_start_time) = (0u64, Instant::now());
+
+    thread::scope(|scope| 
+    {
+        // Each worker plays games with its own searcher and sends them to this thread, which writes them
+        for _ in 0..config.threads 
+        {
+            let sender = sender.clone();
+            let (next_game, stop) = (&next_game, &stop);
+
+            thread::Builder::new()
+                .stack_size(SEARCH_THREAD_STACK_SIZE)
+                .spawn_scoped(scope, move || datagen_worker(config, next_game, stop, sender))
+                .unwrap();
+        }
+
+        drop(sender);
+
+        // Ends when all workers are done
+        for (game_index, game_entries) in receiver 
+        {
+            // Games finished after reaching the limit are discarded
+            if stop.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            // Games are written in order, so the output only depends on the seed and not on the threads
+            finished_games.insert(game_index, game_entries);
+
+            while let Some(game_entries) = finished_games.remove(&progress.next_game)
+            {
+                progress.next_game += 1;
+
+                // Skipped game
+                if game_entries.is_empty() {
+                    continue;
+                }
+
+                // Flush after every game, so the file only ends with a partial game if we are killed while writing
+                for entry in game_entries.iter() {
+                    writer.write(entry).expect("Error writing data");
+                }
+                writer.flush().expect("Error writing data");
+
+                progress.games += 1;
+                progress.positions += game_entries.len() as u64;
+                progress.bytes = writer.get_ref().get_ref().metadata().expect("Error reading file length").len();
+                positions_this_run += game_entries.len() as u64;
+
+                // Saved after the game is flushed, so a resumed run discards anything written after it
+                config.write_metadata(&progress);
+
+                println!("{} | Games: {} | Positions: {} | Positions/sec: {}",
+                    file_path, 
+                    progress.games,
+                    progress.positions, 
+                    positions_this_run * 1000 / milliseconds_elapsed(datagen_start_time).max(1));
+
+                // Stop the workers after the games or positions limit, if any
+                if limit_reached(&progress) {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    });
+
+    println!("Done, wrote {} positions from {} games to {}", progress.positions, progress.games, file_path);
+}
+
+// Plays games until stop is set, sending the index and positions of each game (no positions if skipped)
+fn datagen_worker(config: &DatagenConfig, next_game: &AtomicU64, stop: &AtomicBool, sender: Sender<(u64, Vec<DataEntry>)>)
+{
     let start_board: Board = Board::new(START_FEN);
     let mut searcher = Searcher::new(start_board.clone());
 
-    let mut rng = rand::thread_rng();
-    let mut positions_written: u64 = 0;
-    let datagen_start_time = Instant::now();
-
-    // Infinite loop
-    loop {
+    while !stop.load(Ordering::Relaxed)
+    {
+        let game_index: u64 = next_game.fetch_add(1, Ordering::Relaxed);
+        let mut rng: StdRng = game_rng(config.seed, game_index);
         searcher.board = start_board.clone();
-        let num_random_plies: u8 = rng.gen_range(MIN_PLIES..=MAX_PLIES) as u8;
+        let num_random_plies: u8 = rng.gen_range(config.min_plies..=config.max_plies);
         let mut moves = ArrayVec::<AtaxxMove, 256>::new();
 
         // This loop gets a random opening
@@ -75,10 +524,10 @@ pub fn datagen()
 
                 uainewgame(&mut searcher);
 
-                let score = searcher.search(DEFAULT_MAX_DEPTH, I64_MAX, 0, 
-                                true, SOFT_NODES, HARD_NODES, false).1;
+                let score = searcher.search(DEFAULT_MAX_DEPTH, TimeManager::infinite(), 
+                                config.soft_nodes, config.hard_nodes, false).1;
 
-                if score.abs() > MAX_OPENING_SCORE { 
+                if score.abs() > config.max_opening_score { 
                     searcher.board = start_board.clone();
                     continue;
                 }
@@ -88,19 +537,19 @@ pub fn datagen()
         }
 
         uainewgame(&mut searcher);
-        let mut lines: Vec<String> = Vec::with_capacity(128);
+        let mut entries: Vec<DataEntry> = Vec::with_capacity(128);
         let mut game_state: GameState;
         let mut winner: Color;
 
         // Play out game
         loop {
             searcher.clear_killers();
-            let (mov, score) = searcher.search(DEFAULT_MAX_DEPTH, I64_MAX, 0, 
-                                   true, SOFT_NODES, HARD_NODES, false);
+            let (mov, score) = searcher.search(DEFAULT_MAX_DEPTH, TimeManager::infinite(), 
+                                   config.soft_nodes, config.hard_nodes, false);
             assert!(mov != MOVE_NONE);
 
             // Adjudication
-            if score.abs() >= ADJUDICATION_SCORE {
+            if score.abs() >= config.adjudication_score {
                 game_state = GameState::Won;
                 winner = if score > 0 { 
                     searcher.board.side_to_move()
@@ -111,16 +560,18 @@ pub fn datagen()
                 break;
             }
 
-            // <fen> | <move uai> | <score red/black pov>
-            lines.push(format!("{} | {} | {}", 
-                searcher.board.fen(), 
-                mov,
-                if searcher.board.side_to_move() == Color::Red {score} else {-score}));
+            // Score from red's pov, the result is set when the game ends
+            let red_score: i32 = if searcher.board.side_to_move() == Color::Red {score} else {-score};
+            entries.push(DataEntry::new(&searcher.board, red_score as i16, 0, mov));
 
             searcher.board.make_move(mov);
 
-            // if game over, break
+            // if game over (including threefold repetition), break
             (game_state, winner) = searcher.board.game_state();
+            if game_state == GameState::Ongoing && searcher.board.is_repetition(0) {
+                (game_state, winner) = (GameState::Draw, Color::None);
+            }
+
             if game_state != GameState::Ongoing {
                 break;
             }
@@ -133,61 +584,35 @@ pub fn datagen()
 
         // Skip 100 ply draws since they are bad data
         if searcher.board.plies_since_single() >= 100 {
-            continue;
+            entries.clear();
         }
 
-        // Write data from this game to file
-        for i in 0..lines.len() 
-        {
-            // Append "| <wdl red/black pov>" to the line
-            let line = format!("{} | {}\n", 
-                lines[i], 
-                if winner == Color::Red {
-                    "1.0"
-                } else if winner == Color::Blue {
-                    "0.0"
-                } else {
-                    "0.5"
-                });
-
-            // Write line to file
-            let _ = file.write_all(line.as_bytes());
+        // Result from red's pov
+        let result: u8 = if winner == Color::Red {
+            2
+        } else if winner == Color::Blue {
+            0
+        } else {
+            1
+        };
+
+        for entry in entries.iter_mut() {
+            entry.result = result;
         }
 
-        positions_written += lines.len() as u64;
-        println!("{} | Positions: {} | Positions/sec: {}",
-            file_path, 
-            positions_written, 
-            positions_written * 1000 / milliseconds_elapsed(datagen_start_time));
+        // The writer is gone if it panicked
+        if sender.send((game_index, entries)).is_err() {
+            break;
+        }
     }
-
 }
 
-pub fn datagen_openings()
+pub fn datagen_openings(config: &OpeningsConfig)
 {
-    pub const PLY: usize = 8;
-    pub const SOFT_NODES: u64 = 500_000;
-    pub const HARD_NODES: u64 = 5_000_000;
-    pub const MAX_OPENING_SCORE: i32 = 5;
-
-    // Create 'data' folder if doesnt exist
-    let _ = fs::create_dir_all("data");
-
-    // random file name
-    let file_name: String = (0..12)
-    .map(|_| {
-        let random_index = rand::thread_rng().gen_range(0..CHARACTERS.len());
-        CHARACTERS.chars().nth(random_index).unwrap()
-    })
-    .collect();
-
-    let file_path = format!("data/{}.txt", file_name);
+    config.print();
 
-    // Open the file in write mode, creating it if it doesn't exist
-    let mut file = match File::create(file_path.clone()) {
-        Ok(file) => file,
-        Err(e) => panic!("Error creating file {}: {}", file_path, e),
-    };
+    let file_path: &str = &config.output;
+    let mut file = create_output_file(file_path);
 
     let start_board: Board = Board::new(START_FEN);
     let mut searcher = Searcher::new(start_board.clone());
@@ -196,11 +621,12 @@ pub fn datagen_openings()
     let mut rng = rand::thread_rng();
     let mut moves = ArrayVec::<AtaxxMove, 256>::new();
 
-    // Inifnite loop
-    loop {
+    // Loop until the openings limit, if any
+    while config.max_openings.is_none_or(|max_openings| (zobrist_hashes_written.len() as u64) < max_openings)
+    {
         searcher.board = start_board.clone();
 
-        // This loop gets a random opening with PLY plies
+        // This loop gets a random opening with config.plies plies
         loop {
             // Generate moves and make a random one
             searcher.board.moves(&mut moves);
@@ -215,7 +641,7 @@ pub fn datagen_openings()
             }
 
             // If we made enough moves, break
-            if searcher.board.num_states() - 1 == PLY {
+            if searcher.board.num_states() - 1 == config.plies {
                 break;
             }
         }
@@ -229,10 +655,10 @@ pub fn datagen_openings()
         }     
 
         uainewgame(&mut searcher);
-        let score = searcher.search(DEFAULT_MAX_DEPTH, I64_MAX, 0, 
-                        true, SOFT_NODES, HARD_NODES, false).1;
+        let score = searcher.search(DEFAULT_MAX_DEPTH, TimeManager::infinite(), 
+                        config.soft_nodes, config.hard_nodes, false).1;
 
-        if score.abs() <= MAX_OPENING_SCORE 
+        if score.abs() <= config.max_opening_score 
         {
             // Write fen to file and save zobrist hash
             let line: String = searcher.board.fen() + "\n";
@@ -0,0 +1,358 @@
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use crate::ataxx_move::*;
+use crate::datagen::*;
+use crate::training_data::*;
+use arrayvec::ArrayVec;
+
+pub const DATATOOL_USAGE: &str = "Usage:
+zataxx datatool convert <input> <output>
+zataxx datatool concat <input>... <output>
+zataxx datatool shuffle <input>... <output> [--seed N]
+zataxx datatool dedup <input> <output>
+zataxx datatool filter <input> <output> [--min-plies-since-single N] [--max-plies-since-single N] [--max-score N] [--min-pieces N] [--max-pieces N]
+zataxx datatool stats <input>...
+The output is always last and can't be one of the inputs.
+The format of each file is given by its extension, .bin is binary and anything else is text";
+
+const SCORE_BUCKET_SIZE: i32 = 200;
+const MAX_SCORE_BUCKET: i32 = 1000;
+const GAME_LENGTH_BUCKET_SIZE: usize = 25;
+const MAX_GAME_LENGTH_BUCKET: usize = 200;
+
+pub fn datatool(args: &[String]) -> Result<(), String>
+{
+    let command: &str = args.first().map(|arg| arg.trim()).ok_or("Missing datatool command")?;
+
+    // Files come first, then the options
+    let num_files: usize = args[1..].iter().position(|arg| arg.trim().starts_with("--")).unwrap_or(args.len() - 1);
+    let files: Vec<&str> = args[1..=num_files].iter().map(|arg| arg.trim()).collect();
+    let flags: &[String] = &args[num_files + 1..];
+
+    // Checks the number of files, then splits them into inputs and output (the last file)
+    let split_files = |min: usize, max: usize| -> Result<(&[&str], &str), String> {
+        if files.len() < min || files.len() > max {
+            return Err(format!("Wrong number of files for datatool {}", command));
+        }
+
+        let (output, inputs) = files.split_last().unwrap();
+        check_output(inputs, output)?;
+        Ok((inputs, output))
+    };
+
+    match command {
+        "convert" => {
+            let (inputs, output) = split_files(2, 2)?;
+            parse_flags(flags, |name, _| Err(format!("Unknown option --{}", name)))?;
+            copy_entries(inputs, output, |_| true)
+        }
+        "concat" => {
+            let (inputs, output) = split_files(2, usize::MAX)?;
+            parse_flags(flags, |name, _| Err(format!("Unknown option --{}", name)))?;
+            copy_entries(inputs, output, |_| true)
+        }
+        "shuffle" => {
+            let (inputs, output) = split_files(2, usize::MAX)?;
+            let mut seed: Option<u64> = None;
+
+            parse_flags(flags, |name, value| {
+                match name {
+                    "seed" => seed = Some(parse_flag_value(name, value)?),
+                    _ => return Err(format!("Unknown option --{}", name))
+                }
+                Ok(())
+            })?;
+
+            shuffle(inputs, output, seed)
+        }
+        "dedup" => {
+            let (inputs, output) = split_files(2, 2)?;
+            parse_flags(flags, |name, _| Err(format!("Unknown option --{}", name)))?;
+
+            // Keep the first entry of each position
+            let mut seen_hashes: HashSet<u64> = HashSet::new();
+            copy_entries(inputs, output, |entry| seen_hashes.insert(entry.board_state().zobrist_hash))
+        }
+        "filter" => {
+            let (inputs, output) = split_files(2, 2)?;
+            let filter = DataFilter::from_args(flags)?;
+            copy_entries(inputs, output, |entry| filter.accepts(entry))
+        }
+        "stats" => {
+            if files.is_empty() {
+                return Err(format!("Wrong number of files for datatool {}", command));
+            }
+            parse_flags(flags, |name, _| Err(format!("Unknown option --{}", name)))?;
+
+            let mut stats = DataStats::default();
+            for file_path in files.iter() {
+                read_entries(file_path, |entry| stats.add(&entry))?;
+            }
+            stats.finish();
+            stats.print();
+            Ok(())
+        }
+        _ => Err(format!("Unknown datatool command {}", command))
+    }
+}
+
+// Calls f for each entry in the file, in order
+fn read_entries(file_path: &str, mut f: impl FnMut(DataEntry)) -> Result<(), String>
+{
+    let file = File::open(file_path).map_err(|e| format!("Error opening file {}: {}", file_path, e))?;
+    let reader = DataReader::new(BufReader::new(file), DataFormat::from_path(file_path));
+
+    for (i, entry) in reader.enumerate() {
+        f(entry.map_err(|e| format!("Error reading entry {} of {}: {}", i + 1, file_path, e))?);
+    }
+
+    Ok(())
+}
+
+// Creating the output truncates it, so it can't be one of the inputs
+fn check_output(inputs: &[&str], output: &str) -> Result<(), String>
+{
+    let Ok(output_path) = fs::canonicalize(output) else {
+        return Ok(()); // doesn't exist yet
+    };
+
+    for input in inputs.iter() {
+        if fs::canonicalize(input).is_ok_and(|input_path| input_path == output_path) {
+            return Err(format!("The output {} is also an input", output));
+        }
+    }
+
+    Ok(())
+}
+
+fn create_data_writer(file_path: &str) -> Result<DataWriter<BufWriter<File>>, String>
+{
+    if let Some(parent) = std::path::Path::new(file_path).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+
+    let file = File::create(file_path).map_err(|e| format!("Error creating file {}: {}", file_path, e))?;
+    Ok(DataWriter::new(BufWriter::new(file), DataFormat::from_path(file_path)))
+}
+
+// Writes the entries of the inputs, in order, for which keep returns true
+fn copy_entries(inputs: &[&str], output: &str, mut keep: impl FnMut(&DataEntry) -> bool) -> Result<(), String>
+{
+    let mut writer = create_data_writer(output)?;
+    let mut error: Option<String> = None;
+    let (mut entries_read, mut entries_written) = (0u64, 0u64);
+
+    for input in inputs.iter()
+    {
+        read_entries(input, |entry| {
+            entries_read += 1;
+
+            if error.is_none() && keep(&entry) {
+                match writer.write(&entry) {
+                    Ok(()) => entries_written += 1,
+                    Err(e) => error = Some(format!("Error writing to {}: {}", output, e))
+                }
+            }
+        })?;
+    }
+
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    writer.flush().map_err(|e| format!("Error writing to {}: {}", output, e))?;
+    println!("Read {} entries, wrote {} to {}", entries_read, entries_written, output);
+    Ok(())
+}
+
+// Loads all entries in memory
+fn shuffle(inputs: &[&str], output: &str, seed: Option<u64>) -> Result<(), String>
+{
+    let mut entries: Vec<DataEntry> = Vec::new();
+    for input in inputs.iter() {
+        read_entries(input, |entry| entries.push(entry))?;
+    }
+
+    let mut rng: StdRng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy()
+    };
+    entries.shuffle(&mut rng);
+
+    let mut writer = create_data_writer(output)?;
+    for entry in entries.iter() {
+        writer.write(entry).map_err(|e| format!("Error writing to {}: {}", output, e))?;
+    }
+
+    writer.flush().map_err(|e| format!("Error writing to {}: {}", output, e))?;
+    println!("Shuffled {} entries to {}", entries.len(), output);
+    Ok(())
+}
+
+// Limits are inclusive, the score limit is on its absolute value
+#[derive(Clone, Default)]
+pub struct DataFilter {
+    pub min_plies_since_single: Option<u8>,
+    pub max_plies_since_single: Option<u8>,
+    pub max_score: Option<i16>,
+    pub min_pieces: Option<u32>,
+    pub max_pieces: Option<u32>,
+}
+
+impl DataFilter
+{
+    pub fn from_args(args: &[String]) -> Result<Self, String>
+    {
+        let mut filter = Self::default();
+
+        parse_flags(args, |name, value| {
+            match name {
+                "min-plies-since-single" => filter.min_plies_since_single = Some(parse_flag_value(name, value)?),
+                "max-plies-since-single" => filter.max_plies_since_single = Some(parse_flag_value(name, value)?),
+                "max-score" => filter.max_score = Some(parse_flag_value(name, value)?),
+                "min-pieces" => filter.min_pieces = Some(parse_flag_value(name, value)?),
+                "max-pieces" => filter.max_pieces = Some(parse_flag_value(name, value)?),
+                _ => return Err(format!("Unknown option --{}", name))
+            }
+            Ok(())
+        })?;
+
+        Ok(filter)
+    }
+
+    pub fn accepts(&self, entry: &DataEntry) -> bool
+    {
+        let num_pieces: u32 = entry.num_pieces();
+
+        self.min_plies_since_single.is_none_or(|min| entry.plies_since_single >= min)
+        && self.max_plies_since_single.is_none_or(|max| entry.plies_since_single <= max)
+        && self.max_score.is_none_or(|max| entry.score.unsigned_abs() <= max.unsigned_abs())
+        && self.min_pieces.is_none_or(|min| num_pieces >= min)
+        && self.max_pieces.is_none_or(|max| num_pieces <= max)
+    }
+}
+
+#[derive(Default)]
+pub struct DataStats {
+    pub positions: u64,
+    pub results: [u64; 3], // [result]
+    pub score_histogram: Vec<u64>, // scores below -MAX_SCORE_BUCKET first, at least MAX_SCORE_BUCKET last
+    pub game_lengths: Vec<usize>,
+    previous: Option<DataEntry>,
+    game_length: usize,
+}
+
+impl DataStats
+{
+    // Games aren't stored, so consecutive entries are the same game if the best move of one leads to the next
+    pub fn add(&mut self, entry: &DataEntry)
+    {
+        self.positions += 1;
+        self.results[entry.result as usize] += 1;
+
+        let num_buckets: usize = (MAX_SCORE_BUCKET * 2 / SCORE_BUCKET_SIZE) as usize + 2;
+        self.score_histogram.resize(num_buckets, 0);
+        let bucket: i32 = (entry.score as i32 + MAX_SCORE_BUCKET).div_euclid(SCORE_BUCKET_SIZE) + 1;
+        self.score_histogram[bucket.clamp(0, num_buckets as i32 - 1) as usize] += 1;
+
+        match self.previous {
+            Some(previous) if is_next_entry(&previous, entry) => self.game_length += 1,
+            _ => {
+                self.finish();
+                self.game_length = 1;
+            }
+        }
+
+        self.previous = Some(*entry);
+    }
+
+    // Ends the current game
+    pub fn finish(&mut self)
+    {
+        if self.game_length > 0 {
+            self.game_lengths.push(self.game_length);
+        }
+
+        self.previous = None;
+        self.game_length = 0;
+    }
+
+    pub fn print(&self)
+    {
+        let percentage = |count: u64, total: u64| count as f64 * 100.0 / total.max(1) as f64;
+
+        println!("Positions: {}", self.positions);
+        println!("Results (red's pov): {} red wins ({:.1}%), {} draws ({:.1}%), {} blue wins ({:.1}%)",
+            self.results[2], percentage(self.results[2], self.positions),
+            self.results[1], percentage(self.results[1], self.positions),
+            self.results[0], percentage(self.results[0], self.positions));
+
+        println!("Scores (red's pov):");
+        for (i, count) in self.score_histogram.iter().enumerate()
+        {
+            let min: i32 = -MAX_SCORE_BUCKET + (i as i32 - 1) * SCORE_BUCKET_SIZE;
+            let range: String = if i == 0 {
+                format!("< {}", -MAX_SCORE_BUCKET)
+            } else if i == self.score_histogram.len() - 1 {
+                format!(">= {}", MAX_SCORE_BUCKET)
+            } else {
+                format!("{} to {}", min, min + SCORE_BUCKET_SIZE - 1)
+            };
+
+            println!("    {:>14}: {} ({:.1}%)", range, count, percentage(*count, self.positions));
+        }
+
+        let num_games: u64 = self.game_lengths.len() as u64;
+        let total_length: usize = self.game_lengths.iter().sum();
+        println!("Games: {}, average length {:.1} plies", num_games, total_length as f64 / num_games.max(1) as f64);
+
+        let num_buckets: usize = MAX_GAME_LENGTH_BUCKET / GAME_LENGTH_BUCKET_SIZE + 1;
+        let mut length_histogram: Vec<u64> = vec![0; num_buckets];
+        for length in self.game_lengths.iter() {
+            length_histogram[(length / GAME_LENGTH_BUCKET_SIZE).min(num_buckets - 1)] += 1;
+        }
+
+        println!("Game lengths:");
+        for (i, count) in length_histogram.iter().enumerate()
+        {
+            let min: usize = i * GAME_LENGTH_BUCKET_SIZE;
+            let range: String = if i == length_histogram.len() - 1 {
+                format!(">= {}", min)
+            } else {
+                format!("{} to {}", min, min + GAME_LENGTH_BUCKET_SIZE - 1)
+            };
+
+            println!("    {:>10}: {} ({:.1}%)", range, count, percentage(*count, num_games));
+        }
+    }
+}
+
+// Without a best move (binary data), any legal move may lead to the next entry
+fn is_next_entry(entry: &DataEntry, next: &DataEntry) -> bool
+{
+    if entry.result != next.result {
+        return false;
+    }
+
+    let mut board_state = entry.board_state();
+    let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+
+    if entry.best_move == MOVE_NONE {
+        board_state.moves(&mut moves);
+    } else {
+        moves.push(entry.best_move);
+    }
+
+    moves.iter().any(|mov| {
+        let mut next_board_state = board_state;
+        next_board_state.make_move(*mov);
+
+        next_board_state.color == next.stm
+        && next_board_state.bitboards == [next.red, next.blue]
+        && next_board_state.gaps == next.gaps
+    })
+}
@@ -5,15 +5,21 @@ mod board;
 mod nn;
 mod perft;
 mod tt_entry;
+mod time_manager;
 mod search;
 mod bench;
+mod training_data;
 mod datagen;
+mod datatool;
+mod uai_command;
 mod uai;
 mod tests;
 
 use std::env;
 use uai::*;
 use datagen::*;
+use datatool::*;
+use nn::*;
 
 fn main() {
     println!("Zataxx by zzzzz");
@@ -26,16 +32,56 @@ fn main() {
         println!("Warning: not using avx2");
     }
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // --net <path> loads a net from disk instead of using the embedded one
+    if let Some(i) = args.iter().position(|arg| arg.trim() == "--net") {
+        if i + 1 < args.len() {
+            set_eval_file(args[i + 1].trim());
+            args.drain(i..=i + 1);
+        }
+        else {
+            println!("Missing path after --net. Using embedded net.");
+            args.remove(i);
+        }
+    }
+
     if args.len() > 1 {
         let arg = args[1].trim();
-        if arg == "datagen" {
-            datagen();
+        if arg == "datagen" 
+        {
+            match DatagenConfig::from_args(&args[2..]) {
+                Ok(config) => datagen(&config),
+                Err(error) => println!("{}\n{}", error, DatagenConfig::USAGE)
+            }
             return;
         }
         else if arg == "datagen_openings"
         {
-            datagen_openings();
+            match OpeningsConfig::from_args(&args[2..]) {
+                Ok(config) => datagen_openings(&config),
+                Err(error) => println!("{}\n{}", error, OpeningsConfig::USAGE)
+            }
+            return;
+        }
+        else if arg == "datatool"
+        {
+            if let Err(error) = datatool(&args[2..]) {
+                println!("{}\n{}", error, DATATOOL_USAGE);
+            }
+            return;
+        }
+        else if arg == "convertnet"
+        {
+            if args.len() != 4 {
+                println!("Usage: zataxx convertnet <raw net> <output net>");
+                return;
+            }
+
+            match convert_net(args[2].trim(), args[3].trim()) {
+                Ok(()) => println!("Converted {} to {}", args[2].trim(), args[3].trim()),
+                Err(error) => println!("{}", error)
+            }
             return;
         }
     }
@@ -1,3 +1,7 @@
+use std::alloc::{self, Layout};
+use std::fs;
+use std::mem;
+use std::sync::atomic::{AtomicPtr, Ordering};
 use crate::types::*;
 use crate::utils::*;
 use crate::board::*;
@@ -7,6 +11,12 @@ pub const SCALE: i32 = 400;
 pub const QA: i32 = 255;
 pub const QB: i32 = 64;
 
+// Net files are a header followed by the raw net (as output by bullet)
+pub const NET_MAGIC: [u8; 4] = *b"ZTXN";
+pub const NET_VERSION: u16 = 1;
+pub const FEATURE_SET_2X2_TUPLES: u16 = 1;
+pub const NET_HEADER_SIZE: usize = 24;
+
 #[repr(C)]
 pub struct Net {
     feature_weights: [[i16; HIDDEN_LAYER_SIZE]; 2916],
@@ -15,61 +25,348 @@ pub struct Net {
     output_bias: i16
 }
 
-static NET: Net = unsafe { std::mem::transmute(*include_bytes!("net6.bin")) };
+pub const EMBEDDED_NET_NAME: &str = "<embedded>";
+
+static EMBEDDED_NET: Net = unsafe { std::mem::transmute(*include_bytes!("net6.bin")) };
+
+// The net in use, either the embedded one or one loaded from a file
+static NET: AtomicPtr<Net> = AtomicPtr::new(&EMBEDDED_NET as *const Net as *mut Net);
+
+#[inline]
+fn net() -> &'static Net {
+    unsafe { &*NET.load(Ordering::Relaxed) }
+}
+
+// Must not be called while a search is running, since the previous net may be freed
+fn set_net(new_net: *mut Net)
+{
+    let old_net: *mut Net = NET.swap(new_net, Ordering::Relaxed);
+
+    if !std::ptr::eq(old_net, &EMBEDDED_NET) && old_net != new_net {
+        unsafe { drop(Box::from_raw(old_net)); }
+    }
+}
+
+pub fn use_embedded_net() {
+    set_net(&EMBEDDED_NET as *const Net as *mut Net);
+}
+
+// Loads a net from a file, falling back to the embedded net on error
+pub fn load_net(path: &str) -> Result<(), String>
+{
+    match read_net(path) {
+        Ok(net) => {
+            set_net(Box::into_raw(net));
+            Ok(())
+        }
+        Err(error) => {
+            use_embedded_net();
+            Err(error)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NetHeader {
+    pub version: u16,
+    pub feature_set: u16,
+    pub hidden_size: u16,
+    pub qa: i16,
+    pub qb: i16,
+    pub scale: i16,
+    pub weights_hash: u64
+}
+
+impl NetHeader
+{
+    // Header matching this build's net layout
+    pub fn new(weights: &[u8]) -> Self {
+        Self {
+            version: NET_VERSION,
+            feature_set: FEATURE_SET_2X2_TUPLES,
+            hidden_size: HIDDEN_LAYER_SIZE as u16,
+            qa: QA as i16,
+            qb: QB as i16,
+            scale: SCALE as i16,
+            weights_hash: hash_weights(weights)
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; NET_HEADER_SIZE] 
+    {
+        let mut bytes = [0u8; NET_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&NET_MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.feature_set.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.hidden_size.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.qa.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.qb.to_le_bytes());
+        bytes[14..16].copy_from_slice(&self.scale.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.weights_hash.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> 
+    {
+        if bytes.len() < NET_HEADER_SIZE || bytes[0..4] != NET_MAGIC {
+            return Err(String::from("no net header found (raw nets must be converted with 'convertnet')"));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+
+        Ok(Self {
+            version: u16_at(4),
+            feature_set: u16_at(6),
+            hidden_size: u16_at(8),
+            qa: u16_at(10) as i16,
+            qb: u16_at(12) as i16,
+            scale: u16_at(14) as i16,
+            weights_hash: u64::from_le_bytes(bytes[16..24].try_into().unwrap())
+        })
+    }
+
+    // Checks that the net is compatible with this build and that the weights aren't corrupted
+    pub fn validate(&self, weights: &[u8]) -> Result<(), String>
+    {
+        let expected = Self::new(weights);
+
+        if self.version != expected.version {
+            return Err(format!("net format version is {} but expected {}", self.version, expected.version));
+        }
+        if self.feature_set != expected.feature_set {
+            return Err(format!("input feature set is {} but expected {}", self.feature_set, expected.feature_set));
+        }
+        if self.hidden_size != expected.hidden_size {
+            return Err(format!("hidden layer size is {} but expected {}", self.hidden_size, expected.hidden_size));
+        }
+        if (self.qa, self.qb, self.scale) != (expected.qa, expected.qb, expected.scale) {
+            return Err(format!("QA, QB, SCALE are {}, {}, {} but expected {}, {}, {}", 
+                self.qa, self.qb, self.scale, expected.qa, expected.qb, expected.scale));
+        }
+        if weights.len() != mem::size_of::<Net>() {
+            return Err(format!("weights have {} bytes but expected {}", weights.len(), mem::size_of::<Net>()));
+        }
+        if self.weights_hash != expected.weights_hash {
+            return Err(String::from("weights hash mismatch (corrupted file)"));
+        }
+
+        Ok(())
+    }
+}
+
+// FNV-1a
+fn hash_weights(weights: &[u8]) -> u64 
+{
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in weights {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// Wraps a raw net (e.g. a bullet output) in the net file format
+pub fn convert_net(raw_path: &str, output_path: &str) -> Result<(), String>
+{
+    let weights: Vec<u8> = fs::read(raw_path).map_err(|error| format!("Error reading {}: {}", raw_path, error))?;
+
+    if weights.len() != mem::size_of::<Net>() {
+        return Err(format!("{} has {} bytes, but a raw net with {} hidden neurons has {} bytes", 
+            raw_path, weights.len(), HIDDEN_LAYER_SIZE, mem::size_of::<Net>()));
+    }
+
+    let mut bytes: Vec<u8> = NetHeader::new(&weights).to_bytes().to_vec();
+    bytes.extend_from_slice(&weights);
+
+    fs::write(output_path, bytes).map_err(|error| format!("Error writing {}: {}", output_path, error))
+}
+
+fn read_net(path: &str) -> Result<Box<Net>, String>
+{
+    let bytes: Vec<u8> = fs::read(path).map_err(|error| format!("Error reading {}: {}", path, error))?;
+
+    let header = NetHeader::from_bytes(&bytes).map_err(|error| format!("Invalid net {}: {}", path, error))?;
+    let bytes: &[u8] = &bytes[NET_HEADER_SIZE..];
+    header.validate(bytes).map_err(|error| format!("Invalid net {}: {}", path, error))?;
+
+    // Allocate directly on the heap since the net is too big for the stack
+    unsafe {
+        let layout = Layout::new::<Net>();
+        let net_ptr = alloc::alloc(layout) as *mut Net;
+        if net_ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), net_ptr as *mut u8, bytes.len());
+        Ok(Box::from_raw(net_ptr))
+    }
+}
+
+const NUM_TUPLES: usize = 36;
+const PER_TUPLE: usize = 3usize.pow(4);
+
+// [square] bitmask of the 2x2 tuples containing the square
+// Tuple 6 * i + j has bottom left square 7 * i + j
+const SQUARE_TUPLES: [u64; 49] = square_tuples();
+
+const fn square_tuples() -> [u64; 49]
+{
+    let mut square_tuples = [0u64; 49];
+    let mut tuple: usize = 0;
+
+    while tuple < NUM_TUPLES {
+        let bottom_left: usize = 7 * (tuple / 6) + tuple % 6;
+        square_tuples[bottom_left] |= 1u64 << tuple;
+        square_tuples[bottom_left + 1] |= 1u64 << tuple;
+        square_tuples[bottom_left + 7] |= 1u64 << tuple;
+        square_tuples[bottom_left + 8] |= 1u64 << tuple;
+        tuple += 1;
+    }
+
+    square_tuples
+}
 
-pub fn evaluate(board: &Board) -> i32
+// Index of the input neuron active for a tuple, from the perspective of the side whose pieces are 'us'
+#[inline]
+fn tuple_input(tuple: usize, us: u64, them: u64) -> usize
 {
-    const PER_TUPLE: usize = 3usize.pow(4);
     const POWERS: [usize; 4] = [1, 3, 9, 27];
     const MASK: u64 = 0b0001_1000_0011;
 
-    let mut hidden_layer: [i16; HIDDEN_LAYER_SIZE] = NET.feature_biases;
-    let us = board.us();
-    let them = board.them();
-
-    for i in 0..6 {
-        for j in 0..6 {
-            let tuple = 6 * i + j;
-            let mut input_neuron_idx = PER_TUPLE * tuple;
-            let offset = 7 * i + j;
-            let mut this_us = (us >> offset) & MASK;
-            let mut this_them = (them >> offset) & MASK;
-
-            while this_us > 0 {
-                let mut sq: usize = pop_lsb(&mut this_us) as usize;
-                if sq > 6 { sq -= 5; }
-                input_neuron_idx += POWERS[sq];
-            }
+    let mut input_neuron_idx = PER_TUPLE * tuple;
+    let offset = 7 * (tuple / 6) + tuple % 6;
+    let mut this_us = (us >> offset) & MASK;
+    let mut this_them = (them >> offset) & MASK;
+
+    while this_us > 0 {
+        let mut sq: usize = pop_lsb(&mut this_us) as usize;
+        if sq > 6 { sq -= 5; }
+        input_neuron_idx += POWERS[sq];
+    }
 
-            while this_them > 0 {
-                let mut sq: usize = pop_lsb(&mut this_them) as usize;
-                if sq > 6 { sq -= 5; }
-                input_neuron_idx += 2 * POWERS[sq];
+    while this_them > 0 {
+        let mut sq: usize = pop_lsb(&mut this_them) as usize;
+        if sq > 6 { sq -= 5; }
+        input_neuron_idx += 2 * POWERS[sq];
+    }
+
+    input_neuron_idx
+}
+
+// Hidden layer (before activation) for both perspectives, kept in a stack alongside the board states
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+pub struct Accumulator {
+    perspectives: [[i16; HIDDEN_LAYER_SIZE]; 2], // [color whose pieces are 'us']
+    pub updated: bool
+}
+
+impl Accumulator
+{
+    pub fn default() -> Self {
+        Self {
+            perspectives: [[0; HIDDEN_LAYER_SIZE]; 2],
+            updated: false
+        }
+    }
+
+    pub fn perspective(&self, color: Color) -> &[i16; HIDDEN_LAYER_SIZE] {
+        assert!(self.updated && color != Color::None);
+        &self.perspectives[color as usize]
+    }
+
+    // Computes the accumulator from scratch
+    pub fn refresh(&mut self, bitboards: &[u64; 2]) 
+    {
+        let net: &Net = net();
+
+        for color in [Color::Red, Color::Blue] 
+        {
+            let us: u64 = bitboards[color as usize];
+            let them: u64 = bitboards[opp_color(color) as usize];
+            let hidden_layer = &mut self.perspectives[color as usize];
+            *hidden_layer = net.feature_biases;
+
+            for tuple in 0..NUM_TUPLES {
+                let weights = &net.feature_weights[tuple_input(tuple, us, them)];
+                for (h, &w) in hidden_layer.iter_mut().zip(weights.iter()) {
+                    *h += w;
+                }
             }
+        }
+
+        self.updated = true;
+    }
 
-            for k in 0..HIDDEN_LAYER_SIZE {
-                hidden_layer[k] += NET.feature_weights[input_neuron_idx][k];
+    // Computes the accumulator from an ancestor position's one, 
+    // by updating only the tuples of the squares that changed
+    pub fn update(&mut self, ancestor: &Accumulator, old_bitboards: &[u64; 2], new_bitboards: &[u64; 2])
+    {
+        assert!(ancestor.updated);
+
+        let mut changed_squares: u64 = (old_bitboards[0] ^ new_bitboards[0]) | (old_bitboards[1] ^ new_bitboards[1]);
+        let mut changed_tuples: u64 = 0;
+        while changed_squares > 0 {
+            changed_tuples |= SQUARE_TUPLES[pop_lsb(&mut changed_squares) as usize];
+        }
+
+        // If too much changed, refreshing is faster
+        if changed_tuples.count_ones() > 24 {
+            self.refresh(new_bitboards);
+            return;
+        }
+
+        let net: &Net = net();
+        self.perspectives = ancestor.perspectives;
+
+        while changed_tuples > 0
+        {
+            let tuple: usize = pop_lsb(&mut changed_tuples) as usize;
+
+            for color in [Color::Red, Color::Blue] 
+            {
+                let opp: usize = opp_color(color) as usize;
+                let old_input = tuple_input(tuple, old_bitboards[color as usize], old_bitboards[opp]);
+                let new_input = tuple_input(tuple, new_bitboards[color as usize], new_bitboards[opp]);
+
+                if old_input == new_input { continue; }
+
+                let old_weights = &net.feature_weights[old_input];
+                let new_weights = &net.feature_weights[new_input];
+                let hidden_layer = &mut self.perspectives[color as usize];
+
+                for ((h, &old_w), &new_w) in hidden_layer.iter_mut().zip(old_weights.iter()).zip(new_weights.iter()) {
+                    *h += new_w - old_w;
+                }
             }
         }
+
+        self.updated = true;
     }
+}
+
+pub fn evaluate(board: &mut Board) -> i32
+{
+    let net: &Net = net();
+    let stm: Color = board.side_to_move();
+    let hidden_layer: &[i16; HIDDEN_LAYER_SIZE] = board.accumulator().perspective(stm);
 
     let sum: i32;
 
     #[cfg(not(target_feature = "avx2"))]
     {
-    sum = fallback::flatten(&hidden_layer, &NET.output_weights);
+    sum = fallback::flatten(hidden_layer, &net.output_weights);
     }
 
     #[cfg(target_feature = "avx2")]
     unsafe {
-    sum = avx2::flatten(&hidden_layer, &NET.output_weights);
+    sum = avx2::flatten(hidden_layer, &net.output_weights);
     }
 
-    let eval: i32 = (sum / QA + i32::from(NET.output_bias)) * SCALE / (QA * QB);
+    let eval: i32 = (sum / QA + i32::from(net.output_bias)) * SCALE / (QA * QB);
     eval.clamp(-MIN_WIN_SCORE + 1, MIN_WIN_SCORE - 1)
 }
 
-
 #[cfg(not(target_feature = "avx2"))]
 mod fallback {
     use super::{HIDDEN_LAYER_SIZE, QA};
@@ -1,15 +1,28 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use crate::types::*;
 use crate::utils::*;
 use crate::ataxx_move::*;
 use arrayvec::ArrayVec;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use crate::nn::*;
 use crate::board::*;
 use crate::tt_entry::*;
+use crate::time_manager::*;
 use crate::tunable_params;
 
 pub const DEFAULT_MAX_DEPTH: u8 = 100;
 pub const TT_DEFAULT_MB: usize = 32;
+pub const MAX_THREADS: usize = 256;
+pub const MAX_MULTIPV: usize = 256;
+const CONT_HISTORY_SIZE: usize = 2 * 49 * 49 * 49;
+pub const SEARCH_THREAD_STACK_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_SKILL_LEVEL: u8 = 20;
+const SKILL_MULTIPV: usize = 4;
+const SKILL_TEMPERATURE_PER_LEVEL: f64 = 15.0;
 
 tunable_params! {
     asp_min_depth: u8 = 7, 6, 9, 1;
@@ -33,12 +46,37 @@ tunable_params! {
     history_malus_multiplier: i32 = 300, 50, 600, 50;
     history_malus_max: i32 = 1500, 500, 2500, 100;
     history_max: i32 = 16384, 4096, 32768, 4096;
+    killer_bonus: i32 = 20000, 0, 40000, 2000;
+    counter_move_bonus: i32 = 18000, 0, 40000, 2000;
+    cont_history_weight: f32 = 1.0, 0.0, 2.0, 0.25;
     hard_time_percentage: f64 = 0.5, 0.3, 0.7, 0.1;
     soft_time_percentage: f64 = 0.05, 0.03, 0.07, 0.02;
     soft_time_scale: f64 = 0.6, 0.4, 0.8, 0.1;
+    movestogo_hard_scale: f64 = 3.0, 2.0, 5.0, 0.5;
     nodes_tm_min_depth: u8 = 7, 7, 11, 1;
     nodes_tm_base: f64 = 1.55, 1.3, 1.7, 0.2;
     nodes_tm_multiplier: f64 = 1.5, 1.3, 1.7, 0.2;
+    bm_stability_max: u8 = 6, 3, 10, 1;
+    bm_stability_base: f64 = 1.4, 1.1, 1.8, 0.1;
+    bm_stability_multiplier: f64 = 0.1, 0.05, 0.2, 0.025;
+    score_drop_multiplier: f64 = 0.01, 0.005, 0.03, 0.005;
+    score_drop_min_scale: f64 = 0.8, 0.6, 1.0, 0.05;
+    score_drop_max_scale: f64 = 1.6, 1.2, 2.0, 0.1;
+    qs_min_captures: u8 = 3, 2, 6, 1;
+    qs_max_plies: u8 = 4, 1, 8, 1;
+}
+
+// Continuation history is indexed by the previous move's destination only, 
+// since indexing by both its squares would make the table too big
+#[inline]
+fn cont_history_index(stm: usize, previous_move: AtaxxMove, mov: AtaxxMove) -> usize {
+    ((stm * 49 + previous_move.to as usize) * 49 + mov.from as usize) * 49 + mov.to as usize
+}
+
+#[derive(Clone)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: ArrayVec<AtaxxMove, 256>
 }
 
 pub struct Searcher {
@@ -46,45 +84,100 @@ pub struct Searcher {
     max_depth: u8,
     max_ply_reached: u8,
     nodes: u64,
+    qs_nodes: u64,
+    qsearch_enabled: bool,
     start_time: Instant,
-    hard_milliseconds: u64,
-    soft_milliseconds: u64,
+    time_manager: TimeManager,
+    move_overhead: u64, // UAI MoveOverhead option, in milliseconds
     hard_nodes: u64,
     soft_nodes: u64,
     best_move_root: AtaxxMove,
     root_move_nodes: [u64; 1usize << 13],
-    tt: Vec<TTEntry>,
+    completed_depth: u8,
+    multipv: usize,
+    limit_strength: bool, // UAI_LimitStrength option
+    skill_level: u8, // Skill Level option, only used with UAI_LimitStrength
+    search_moves: ArrayVec<AtaxxMove, 256>, // 'go searchmoves', root moves to search (all if empty)
+    excluded_root_moves: ArrayVec<AtaxxMove, 256>, // root moves of the previous MultiPV lines
+    pv_lines: Vec<PvLine>, // from the last completed iteration, best first
+    tt: Arc<TT>,
+    tt_stats: TTStats, // of the last search, including helper threads
     evals: [i32; 256],
     lmr_table: [[u8; 256]; 256],
     killers: [AtaxxMove; 256],
+    pv_table: [[AtaxxMove; 256]; 256], // triangular PV table [ply][i]
+    pv_lengths: [u8; 256], // [ply]
     history: [[[i32; 49]; 49]; 2], // [color][move.from][move.to]
+    counter_moves: [[AtaxxMove; 49]; 49], // [previous_move.from][previous_move.to]
+    cont_history: Vec<i32>, // [color][previous_move.to][move.from][move.to], see cont_history_index()
+    stop: Arc<AtomicBool>,
+    show_wdl: bool, // UAI_ShowWDL option, info lines also report win/draw/loss probabilities
+    ponder_enabled: bool, // UAI Ponder option, bestmove also sends the expected reply
+    ponder: Arc<AtomicBool>, // set by 'go ponder', cleared by 'ponderhit'
+    pondering: bool, // this search is pondering and hasn't seen the ponderhit yet
+    infinite: bool, // 'go infinite', the result can't be sent before 'stop'
+    published_nodes: Arc<AtomicU64>, // this thread's nodes, readable by the main thread during the search
+    is_helper: bool,
+    helpers: Vec<Searcher>, // Lazy SMP helper threads' searchers
+    helpers_nodes: Vec<Arc<AtomicU64>>,
 }
 
 impl Searcher
 {
     pub fn new(board: Board) -> Self 
+    {
+        let mut searcher = Self::new_helper(board, Arc::new(TT::new(TT_DEFAULT_MB)), 
+                                Arc::new(AtomicBool::new(false)));
+        searcher.init_lmr_table();
+        searcher
+    }
+
+    fn new_helper(board: Board, tt: Arc<TT>, stop: Arc<AtomicBool>) -> Self 
     {
         let mut searcher = Self {
             board: board.clone(),
             max_depth: DEFAULT_MAX_DEPTH,
             max_ply_reached: 0,
             nodes: 0,
+            qs_nodes: 0,
+            qsearch_enabled: true,
             start_time: Instant::now(),
-            hard_milliseconds: U64_MAX,
-            soft_milliseconds: U64_MAX,
+            time_manager: TimeManager::infinite(),
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
             hard_nodes: U64_MAX,
             soft_nodes: U64_MAX,
             best_move_root: MOVE_NONE,
             root_move_nodes: [0; 1usize << 13],
-            tt: vec![TTEntry::default(); 0],
+            completed_depth: 0,
+            multipv: 1,
+            limit_strength: false,
+            skill_level: MAX_SKILL_LEVEL,
+            search_moves: ArrayVec::new(),
+            excluded_root_moves: ArrayVec::new(),
+            pv_lines: Vec::new(),
+            tt,
+            tt_stats: TTStats::default(),
             evals: [0; 256],
             lmr_table: [[0; 256]; 256],
             killers: [MOVE_NONE; 256],
+            pv_table: [[MOVE_NONE; 256]; 256],
+            pv_lengths: [0; 256],
             history: [[[0; 49]; 49]; 2],
+            counter_moves: [[MOVE_NONE; 49]; 49],
+            cont_history: vec![0; CONT_HISTORY_SIZE],
+            stop,
+            show_wdl: false,
+            ponder_enabled: false,
+            ponder: Arc::new(AtomicBool::new(false)),
+            pondering: false,
+            infinite: false,
+            published_nodes: Arc::new(AtomicU64::new(0)),
+            is_helper: false,
+            helpers: Vec::new(),
+            helpers_nodes: Vec::new(),
         };
 
         searcher.init_lmr_table();
-        searcher.resize_tt(TT_DEFAULT_MB);
         searcher
     }
 
@@ -96,46 +189,219 @@ impl Searcher
                 self.lmr_table[depth][move_num] = value.round().clamp(0.0, 255.0) as u8;
             }
         }
+
+        for helper in self.helpers.iter_mut() {
+            helper.lmr_table = self.lmr_table;
+        }
     }
 
     pub fn resize_tt(&mut self, size_mb: usize)
     {
-        let num_entries: usize = (size_mb * 1024 * 1024 / std::mem::size_of::<TTEntry>()) as usize;
-        self.tt = vec![TTEntry::default(); num_entries];
+        // Free the old TT before allocating the new one
+        self.tt = Arc::new(TT::new(0));
+        for helper in self.helpers.iter_mut() {
+            helper.tt = Arc::clone(&self.tt);
+        }
+
+        self.tt = Arc::new(TT::new(size_mb));
+        for helper in self.helpers.iter_mut() {
+            helper.tt = Arc::clone(&self.tt);
+        }
+    }
+
+    pub fn print_tt_stats(&self) {
+        println!("TT stats of the last search");
+        self.tt_stats.print();
+        println!("Hashfull: {} permille", self.tt.hashfull());
     }
 
     pub fn print_tt_size(&self) {
-        let size_bytes: usize = self.tt.len() * std::mem::size_of::<TTEntry>();
-        let size_mb: f64 = size_bytes as f64 / (1024.0 * 1024.0);
+        let size_mb: f64 = self.tt.size_bytes() as f64 / (1024.0 * 1024.0);
         println!("TT size: {} MB ({} entries)", size_mb.round(), self.tt.len());    
     }
 
+    // Total number of search threads, including this (main) one
+    pub fn set_threads(&mut self, num_threads: usize)
+    {
+        assert!(num_threads > 0);
+        self.helpers.truncate(num_threads - 1);
+
+        while self.helpers.len() < num_threads - 1 {
+            let mut helper = Self::new_helper(self.board.clone(), Arc::clone(&self.tt), Arc::clone(&self.stop));
+            helper.lmr_table = self.lmr_table;
+            helper.qsearch_enabled = self.qsearch_enabled;
+            helper.is_helper = true;
+            self.helpers.push(helper);
+        }
+
+        self.helpers_nodes = self.helpers.iter().map(|helper| Arc::clone(&helper.published_nodes)).collect();
+    }
+
+    pub fn set_multipv(&mut self, multipv: usize) {
+        assert!(multipv > 0);
+        self.multipv = multipv;
+    }
+
+    pub fn set_limit_strength(&mut self, enabled: bool) {
+        self.limit_strength = enabled;
+    }
+
+    pub fn set_skill_level(&mut self, skill_level: u8) {
+        assert!(skill_level <= MAX_SKILL_LEVEL);
+        self.skill_level = skill_level;
+    }
+
+    fn is_skill_limited(&self) -> bool {
+        self.limit_strength && self.skill_level < MAX_SKILL_LEVEL
+    }
+
+    // Restricts the root to these moves for the next search only
+    pub fn set_search_moves(&mut self, search_moves: ArrayVec<AtaxxMove, 256>) {
+        self.search_moves = search_moves;
+    }
+
+    // For the next search only
+    pub fn set_infinite(&mut self, infinite: bool) {
+        self.infinite = infinite;
+    }
+
+    pub fn set_move_overhead(&mut self, milliseconds: u64) {
+        self.move_overhead = milliseconds;
+    }
+
+    pub fn move_overhead(&self) -> u64 {
+        self.move_overhead
+    }
+
+    pub fn set_show_wdl(&mut self, enabled: bool) {
+        self.show_wdl = enabled;
+    }
+
+    pub fn set_ponder(&mut self, enabled: bool) {
+        self.ponder_enabled = enabled;
+    }
+
+    pub fn is_ponder_enabled(&self) -> bool {
+        self.ponder_enabled
+    }
+
+    // Best reply to the best move, from the PV or else the TT
+    pub fn ponder_move(&mut self, best_move: AtaxxMove) -> AtaxxMove
+    {
+        if let Some(line) = self.pv_lines.first() {
+            if line.pv.len() >= 2 && line.pv[0] == best_move {
+                return line.pv[1];
+            }
+        }
+
+        self.board.make_move(best_move);
+        let mut ponder_move: AtaxxMove = MOVE_NONE;
+
+        if self.board.game_state().0 != GameState::Ongoing {
+            self.board.undo_move();
+            return MOVE_NONE;
+        }
+
+        if let Some(tt_entry) = self.tt.probe(self.board.zobrist_hash()) {
+            let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+            self.board.moves(&mut moves);
+
+            if moves.contains(&tt_entry.get_move()) {
+                ponder_move = tt_entry.get_move();
+            }
+        }
+
+        self.board.undo_move();
+        ponder_move
+    }
+
+    pub fn set_qsearch(&mut self, enabled: bool) {
+        self.qsearch_enabled = enabled;
+        for helper in self.helpers.iter_mut() {
+            helper.qsearch_enabled = enabled;
+        }
+    }
+
+    pub fn is_qsearch_enabled(&self) -> bool { self.qsearch_enabled }
+
     pub fn get_nodes(&self) -> u64 { self.nodes }
 
+    // Nodes searched in quiescence search (already included in get_nodes())
+    pub fn get_qs_nodes(&self) -> u64 { self.qs_nodes }
+
+    // Nodes searched by all threads
+    pub fn total_nodes(&self) -> u64 {
+        self.helpers_nodes.iter().fold(self.nodes, |total, nodes| total + nodes.load(Ordering::Relaxed))
+    }
+
+    // Shared flag that makes the search return asap when set (e.g. by the 'stop' command)
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
     pub fn clear_tt(&mut self) { 
-        for i in 0..self.tt.len() {
-            self.tt[i] = TTEntry::default();
-        }
+        self.tt.clear();
     }
 
     pub fn clear_killers(&mut self) {
         self.killers = [MOVE_NONE; 256];
+        for helper in self.helpers.iter_mut() {
+            helper.clear_killers();
+        }
     }
 
     pub fn clear_history(&mut self) {
         self.history = [[[0; 49]; 49]; 2];
+        self.counter_moves = [[MOVE_NONE; 49]; 49];
+        self.cont_history.fill(0);
+        for helper in self.helpers.iter_mut() {
+            helper.clear_history();
+        }
+    }
+
+    // Shared flag that makes the search ignore time limits while set
+    pub fn ponder_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.ponder)
     }
 
     pub fn milliseconds_elapsed(&self) -> u64 {
         milliseconds_elapsed(self.start_time)
     }
 
-    pub fn is_hard_time_up(&self) -> bool 
+    // On ponderhit, the time limits start counting from now
+    fn is_pondering(&mut self) -> bool
+    {
+        if self.pondering && !self.ponder.load(Ordering::Relaxed) 
+        {
+            self.pondering = false;
+            let ms_elapsed: u64 = self.milliseconds_elapsed();
+            self.time_manager.delay(ms_elapsed);
+        }
+
+        self.pondering
+    }
+
+    // When pondering, the search result can't be sent before 'ponderhit' or 'stop',
+    // and in 'go infinite' it can't be sent before 'stop'
+    fn wait_for_result_allowed(&self) 
+    {
+        while (self.infinite || self.pondering && self.ponder.load(Ordering::Relaxed))
+        && !self.stop.load(Ordering::Relaxed) 
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    pub fn is_hard_time_up(&mut self) -> bool 
     {
         if self.best_move_root == MOVE_NONE {
             return false;
         }
 
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+
         if self.nodes >= self.hard_nodes { 
             return true; 
         }
@@ -144,61 +410,204 @@ impl Searcher
             return false;
         }
 
-        self.milliseconds_elapsed() >= self.hard_milliseconds
+        self.published_nodes.store(self.nodes, Ordering::Relaxed);
+
+        !self.is_pondering() && self.milliseconds_elapsed() >= self.time_manager.hard_milliseconds()
     }
 
-    pub fn search(&mut self, max_depth: u8, milliseconds: i64, increment_ms: u64, is_move_time: bool, 
+    pub fn search(&mut self, max_depth: u8, time_manager: TimeManager, 
         soft_nodes: u64, hard_nodes: u64, print_info: bool) -> (AtaxxMove, i32)
     {
+        // Skill level: lower levels search shallower and fewer nodes
+        let (max_depth, hard_nodes) = if self.is_skill_limited() {
+            (max_depth.min(1 + self.skill_level / 2), hard_nodes.min(1000 << (self.skill_level / 2)))
+        } else {
+            (max_depth, hard_nodes)
+        };
+
         // init/reset stuff
         self.start_time = Instant::now();
         self.max_depth = max_depth;
+        self.time_manager = time_manager;
         self.soft_nodes = soft_nodes;
         self.hard_nodes = hard_nodes;
         self.nodes = 0;
+        self.qs_nodes = 0;
+        self.published_nodes.store(0, Ordering::Relaxed);
         self.best_move_root = MOVE_NONE;
         self.root_move_nodes = [0; 1usize << 13];
+        self.tt_stats = TTStats::default();
+        self.pondering = !self.is_helper && self.ponder.load(Ordering::Relaxed);
 
-        // Set time limits
-        let max_hard_ms: u64 = (milliseconds - 10).max(0) as u64;
-        if is_move_time {
-            self.hard_milliseconds = max_hard_ms;
-            self.soft_milliseconds = U64_MAX;
+        if !self.is_helper {
+            self.tt.increment_age();
         }
-        else {
-            self.hard_milliseconds = (max_hard_ms as f64 * hard_time_percentage()) as u64;
-            let mut soft_milliseconds: f64 = max_hard_ms as f64 * soft_time_percentage() + increment_ms as f64 * 0.6666;
-            soft_milliseconds *= soft_time_scale();
-            self.soft_milliseconds = (soft_milliseconds as u64).min(self.hard_milliseconds);
+
+        for nodes in self.helpers_nodes.iter() {
+            nodes.store(0, Ordering::Relaxed);
         }
 
+        // With a skill level, helpers (which search at full strength) could complete deeper and replace the skill move
+        if self.helpers.is_empty() || self.is_skill_limited() {
+            let result = self.iterative_deepening(print_info);
+            self.wait_for_result_allowed();
+            self.search_moves.clear();
+            self.infinite = false;
+            return result;
+        }
+
+        // Lazy SMP: helper threads search the same position with no limits, sharing the TT,
+        // until this (main) thread is done and tells them to stop
+        let mut helpers: Vec<Searcher> = std::mem::take(&mut self.helpers);
+
+        let (main_result, helpers_results) = thread::scope(|scope| 
+        {
+            let handles: Vec<_> = helpers.iter_mut().map(|helper| 
+            {
+                helper.board = self.board.clone();
+                helper.search_moves = self.search_moves.clone();
+
+                thread::Builder::new()
+                    .stack_size(SEARCH_THREAD_STACK_SIZE)
+                    .spawn_scoped(scope, move || 
+                        helper.search(max_depth, TimeManager::infinite(), U64_MAX, U64_MAX, false))
+                    .unwrap()
+            })
+            .collect();
+
+            let main_result = self.iterative_deepening(print_info);
+            self.wait_for_result_allowed();
+            self.stop.store(true, Ordering::Relaxed);
+
+            let helpers_results: Vec<(AtaxxMove, i32)> = handles.into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect();
+
+            (main_result, helpers_results)
+        });
+
+        // Use the result of the thread that completed the deepest iteration (ties go to the main thread).
+        // With MultiPV, helpers search a single line and get deeper, so the main thread's 1st line is used.
+        let (mut best_move, mut score) = main_result;
+        let mut best_depth: u8 = self.completed_depth;
+        for (helper, helper_result) in helpers.iter().zip(helpers_results) {
+            if self.multipv == 1 && helper.completed_depth > best_depth {
+                best_depth = helper.completed_depth;
+                (best_move, score) = helper_result;
+            }
+
+            self.tt_stats.add(&helper.tt_stats);
+        }
+
+        self.helpers = helpers;
+        self.stop.store(false, Ordering::Relaxed);
+        self.search_moves.clear();
+        self.infinite = false;
+
+        (best_move, score)
+    }
+
+    fn iterative_deepening(&mut self, print_info: bool) -> (AtaxxMove, i32)
+    {
+        // Number of PV lines to search, can't exceed the number of legal root moves
+        let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+        self.board.moves(&mut moves);
+        let num_root_moves: usize = if self.search_moves.is_empty() { moves.len() } else { self.search_moves.len() };
+        let multipv: usize = if self.is_skill_limited() { self.multipv.max(SKILL_MULTIPV) } else { self.multipv };
+        let num_lines: usize = multipv.min(num_root_moves);
+
         // ID (Iterative deepening)
-        let mut score: i32 = 0;
-        for iteration_depth in 1..=self.max_depth 
+        self.pv_lines.clear();
+        self.completed_depth = 0;
+        let mut best_move_stability: u8 = 0; // iterations in a row with the same best move
+        'id: for iteration_depth in 1..=self.max_depth 
         {
             self.max_ply_reached = 0;
-            let iteration_score = if iteration_depth >= asp_min_depth() {
-                self.aspiration(iteration_depth, score)
+            self.excluded_root_moves.clear();
+            let mut iteration_lines: Vec<PvLine> = Vec::with_capacity(num_lines);
+
+            // MultiPV: each line excludes the root moves of the previous ones
+            for pv_idx in 0..num_lines
+            {
+                self.best_move_root = MOVE_NONE;
+                let previous_score: i32 = self.pv_lines.get(pv_idx).map_or(0, |line| line.score);
+
+                let line_score = if iteration_depth >= asp_min_depth() {
+                    self.aspiration(iteration_depth, previous_score)
+                }
+                else {
+                    self.pvs(iteration_depth as i32, 0, -INFINITY, INFINITY, false)
+                };
+
+                if self.is_hard_time_up() 
+                {
+                    // Stopped in the 1st iteration: use the lines it completed, if any, since the
+                    // partial line excludes their (better) moves
+                    if self.pv_lines.is_empty() {
+                        iteration_lines.sort_by_key(|line| std::cmp::Reverse(line.score));
+                        self.pv_lines = iteration_lines;
+                    }
+
+                    break 'id; 
+                }
+
+                assert!(self.best_move_root != MOVE_NONE);
+                self.excluded_root_moves.push(self.best_move_root);
+
+                iteration_lines.push(PvLine { 
+                    score: line_score, 
+                    pv: self.pv(iteration_depth) 
+                });
             }
-            else {
-                self.pvs(iteration_depth as i32, 0, -INFINITY, INFINITY, false)
-            };
 
-            if self.is_hard_time_up() { break; }
+            // Sort lines by score (stable sort, so equal scores keep their search order)
+            iteration_lines.sort_by_key(|line| std::cmp::Reverse(line.score));
+            let previous_best_line: Option<PvLine> = self.pv_lines.first().cloned();
+            self.pv_lines = iteration_lines;
+            self.completed_depth = iteration_depth;
+            let best_move: AtaxxMove = self.pv_lines[0].pv[0];
+            let score: i32 = self.pv_lines[0].score;
 
-            assert!(self.best_move_root != MOVE_NONE);
-            score = iteration_score;
+            if previous_best_line.as_ref().is_some_and(|line| line.pv[0] == best_move) {
+                best_move_stability = (best_move_stability + 1).min(bm_stability_max());
+            }
+            else {
+                best_move_stability = 0;
+            }
             let ms_elapsed = self.milliseconds_elapsed();
 
+            // The skill level may search more lines than requested, only the requested ones are printed
             if print_info {
-                println!("info depth {} seldepth {} score {} time {} nodes {} nps {} pv {}",
-                    iteration_depth, 
-                    self.max_ply_reached,
-                    iteration_score,
-                    ms_elapsed, 
-                    self.nodes,
-                    self.nodes * 1000 / ms_elapsed.max(1),
-                    self.best_move_root);
+                let num_printed_lines: usize = self.multipv.min(num_lines);
+
+                for (i, line) in self.pv_lines.iter().take(num_printed_lines).enumerate() 
+                {
+                    let pv: Vec<String> = line.pv.iter().map(|mov| mov.to_string()).collect();
+
+                    let wdl: String = if self.show_wdl {
+                        let (win, draw, loss) = wdl_model(line.score);
+                        format!(" wdl {} {} {}", win, draw, loss)
+                    } else {
+                        String::new()
+                    };
+
+                    println!("info {}depth {} seldepth {} score {}{} time {} nodes {} nps {} hashfull {} pv {}",
+                        if num_printed_lines > 1 { format!("multipv {} ", i + 1) } else { String::new() },
+                        iteration_depth, 
+                        self.max_ply_reached,
+                        score_to_string(line.score),
+                        wdl,
+                        ms_elapsed, 
+                        self.total_nodes(),
+                        self.total_nodes() * 1000 / ms_elapsed.max(1),
+                        self.tt.hashfull(),
+                        pv.join(" "));
+                }
+            }
+
+            // While pondering, keep searching until 'ponderhit' or 'stop'
+            if self.is_pondering() {
+                continue;
             }
 
             // Check soft nodes
@@ -210,21 +619,29 @@ impl Searcher
 
             let updated_soft_milliseconds: u64 = if iteration_depth >= nodes_tm_min_depth()
             {
-                let best_move_nodes_fraction: f64 = if self.best_move_root == MOVE_PASS { 
+                let best_move_nodes_fraction: f64 = if best_move == MOVE_PASS { 
                     1.0 
                 } 
                 else {
-                    self.root_move_nodes[self.best_move_root.to_u12() as usize] as f64 
+                    self.root_move_nodes[best_move.to_u12() as usize] as f64 
                     / self.nodes.max(1) as f64
                 };
 
-                (self.soft_milliseconds as f64 
-                * (nodes_tm_base() - best_move_nodes_fraction)
-                * nodes_tm_multiplier())
-                as u64
+                let nodes_scale: f64 = (nodes_tm_base() - best_move_nodes_fraction) * nodes_tm_multiplier();
+
+                // Less time if the best move keeps being the same
+                let stability_scale: f64 = bm_stability_base() 
+                                           - bm_stability_multiplier() * best_move_stability as f64;
+
+                // More time if the score dropped since the last iteration, less if it went up
+                let previous_score: i32 = previous_best_line.map_or(score, |line| line.score);
+                let score_drop_scale: f64 = (1.0 + (previous_score - score) as f64 * score_drop_multiplier())
+                                            .clamp(score_drop_min_scale(), score_drop_max_scale());
+
+                self.time_manager.scaled_soft_milliseconds(nodes_scale * stability_scale * score_drop_scale)
             }
             else {
-                self.soft_milliseconds
+                self.time_manager.soft_milliseconds()
             };
 
             if ms_elapsed >= updated_soft_milliseconds { 
@@ -232,8 +649,43 @@ impl Searcher
             }
         }
 
-        assert!(self.best_move_root != MOVE_NONE);
-        (self.best_move_root, score)
+        // If we were stopped before completing the 1st line of the 1st iteration, use the partial result
+        if self.pv_lines.is_empty() {
+            assert!(self.best_move_root != MOVE_NONE);
+            return (self.best_move_root, 0);
+        }
+
+        if self.is_skill_limited() {
+            return self.skill_move();
+        }
+
+        (self.pv_lines[0].pv[0], self.pv_lines[0].score)
+    }
+
+    // Picks one of the PV lines at random, the bigger its score gap to the best line, the less likely.
+    // Lower skill levels have a higher temperature, so they pick worse lines more often.
+    // The random generator is seeded by the position, so the same position and level give the same move.
+    fn skill_move(&self) -> (AtaxxMove, i32)
+    {
+        let temperature: f64 = (MAX_SKILL_LEVEL - self.skill_level) as f64 * SKILL_TEMPERATURE_PER_LEVEL;
+        let best_score: i32 = self.pv_lines[0].score;
+
+        let weights: Vec<f64> = self.pv_lines.iter()
+            .map(|line| ((line.score - best_score) as f64 / temperature).exp())
+            .collect();
+
+        let mut rng = StdRng::seed_from_u64(self.board.zobrist_hash() ^ self.skill_level as u64);
+        let mut random: f64 = rng.gen::<f64>() * weights.iter().sum::<f64>();
+
+        for (line, weight) in self.pv_lines.iter().zip(weights) 
+        {
+            if random < weight {
+                return (line.pv[0], line.score);
+            }
+            random -= weight;
+        }
+
+        (self.pv_lines[0].pv[0], self.pv_lines[0].score)
     }
 
     fn aspiration(&mut self, iteration_depth: u8, mut score: i32) -> i32
@@ -268,12 +720,54 @@ impl Searcher
         score
     }
 
+    // The root PV from the triangular PV table. If it's shorter than the iteration depth 
+    // (e.g. cut short by a TT cutoff), it's extended with TT moves.
+    pub fn pv(&mut self, depth: u8) -> ArrayVec<AtaxxMove, 256>
+    {
+        let mut pv = ArrayVec::<AtaxxMove, 256>::new();
+        for &mov in self.pv_table[0].iter().take(self.pv_lengths[0] as usize) {
+            pv.push(mov);
+            self.board.make_move(mov);
+        }
+
+        let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+
+        while pv.len() < depth as usize && self.board.game_state().0 == GameState::Ongoing
+        {
+            let Some(tt_entry) = self.tt.probe(self.board.zobrist_hash()) else { break; };
+
+            // The TT move may be illegal in case of a hash collision
+            let tt_move: AtaxxMove = tt_entry.get_move();
+            self.board.moves(&mut moves);
+            if !moves.contains(&tt_move) { break; }
+
+            pv.push(tt_move);
+            self.board.make_move(tt_move);
+        }
+
+        for _ in 0..pv.len() {
+            self.board.undo_move();
+        }
+
+        pv
+    }
+
+    #[inline]
+    fn store_tt(&mut self, tt_entry: TTEntry)
+    {
+        self.tt_stats.stores[tt_entry.get_bound() as usize] += 1;
+
+        if self.tt.store(self.board.zobrist_hash(), tt_entry) {
+            self.tt_stats.overwrites += 1;
+        }
+    }
+
     #[inline]
     fn eval(&mut self, ply: u8) -> i32 
     {
         let ply = ply as usize;
         if self.evals[ply] == EVAL_NONE {
-            self.evals[ply] = evaluate(&self.board);
+            self.evals[ply] = evaluate(&mut self.board);
         }
         self.evals[ply]
     }
@@ -283,6 +777,10 @@ impl Searcher
     {
         if self.is_hard_time_up() { return 0; }
 
+        if !singular {
+            self.pv_lengths[ply as usize] = 0;
+        }
+
         // Update seldepth
         if ply > self.max_ply_reached {
             self.max_ply_reached = ply;
@@ -290,6 +788,8 @@ impl Searcher
 
         // Game over?
         if ply > 0 && !singular {
+            if self.board.is_repetition(ply) { return 0; }
+
             let (game_state, winner): (GameState, Color) = self.board.game_state();
 
             if game_state == GameState::Draw { return 0 };
@@ -303,9 +803,13 @@ impl Searcher
             }
         }
 
-        // Leaf node, return static eval
+        // Leaf node, return static eval or quiescence search score
         if depth <= 0 || ply >= self.max_depth { 
-            return evaluate(&self.board);
+            return if self.qsearch_enabled && ply < self.max_depth {
+                self.qsearch(ply, 0, alpha, beta)
+            } else {
+                evaluate(&mut self.board)
+            };
         }
 
         if depth > self.max_depth.into() { 
@@ -313,9 +817,11 @@ impl Searcher
         }
 
         // Probe TT
-        let tt_entry_index = self.board.zobrist_hash() as usize % self.tt.len();
-        let tt_entry: TTEntry = self.tt[tt_entry_index];
-        let tt_hit: bool = self.board.zobrist_hash() == tt_entry.zobrist_hash;
+        let tt_probe: Option<TTEntry> = self.tt.probe(self.board.zobrist_hash());
+        let tt_hit: bool = tt_probe.is_some();
+        self.tt_stats.probes += 1;
+        self.tt_stats.hits += tt_hit as u64;
+        let tt_entry: TTEntry = tt_probe.unwrap_or(TTEntry::new(0, 0, MOVE_NONE, Bound::None));
 
         // TT cutoff
         if tt_hit 
@@ -352,29 +858,59 @@ impl Searcher
 
         let stm: usize = self.board.side_to_move() as usize;
 
+        // Opponent's move that led to this position, for counter move and continuation history
+        let previous_move: AtaxxMove = self.board.last_move();
+        let has_previous_move: bool = previous_move != MOVE_NONE && previous_move != MOVE_PASS;
+        let counter_move: AtaxxMove = if has_previous_move { 
+            self.counter_moves[previous_move.from as usize][previous_move.to as usize] 
+        } else { 
+            MOVE_NONE 
+        };
+
         // Generate moves
         let mut moves = ArrayVec::<AtaxxMove, 256>::new();
         self.board.moves(&mut moves);
 
         // Score moves
         let mut moves_scores = ArrayVec::<i32, 256>::new();
+        let mut tt_move_found: bool = false;
         if moves.len() > 1 {
             for i in 0..(moves.len() as usize) { 
                 let mov: AtaxxMove = moves[i];
                 if mov == tt_move {
                     moves_scores.push(I32_MAX);
+                    tt_move_found = true;
                 }
                 else {
                     let mut move_score: i32 = mov.is_single() as i32 * 2;
                     move_score += self.board.num_adjacent_enemies(mov.to) as i32;
                     move_score *= 1_000_000;
                     move_score += self.history[stm][mov.from as usize][mov.to as usize];
+
+                    if has_previous_move {
+                        let cont_history = self.cont_history[cont_history_index(stm, previous_move, mov)];
+                        move_score += (cont_history as f32 * cont_history_weight()) as i32;
+                    }
+
+                    if mov == self.killers[ply as usize] {
+                        move_score += killer_bonus();
+                    }
+                    else if mov == counter_move {
+                        move_score += counter_move_bonus();
+                    }
+
                     moves_scores.push(move_score);
                 }
             }
         }
         else {
             moves_scores.push(0);
+            tt_move_found = moves[0] == tt_move;
+        }
+
+        // An illegal TT move means the TT entry is from another position with the same key
+        if tt_move != MOVE_NONE && !tt_move_found {
+            self.tt_stats.illegal_moves += 1;
         }
 
         let mut best_score: i32 = -INFINITY;
@@ -388,6 +924,12 @@ impl Searcher
             // Don't search the excluded TT move in a singular search
             if mov == tt_move && singular { continue; }
 
+            // MultiPV: don't search the root moves of the previous lines
+            if ply == 0 && self.excluded_root_moves.contains(&mov) { continue; }
+
+            // Searchmoves: only search the given root moves
+            if ply == 0 && !self.search_moves.is_empty() && !self.search_moves.contains(&mov) { continue; }
+
             if ply > 0 && best_score > -MIN_WIN_SCORE
             {
                 // LMP (Late move pruning)
@@ -426,7 +968,7 @@ impl Searcher
                     return singular_beta;
                 }
                 // Negative extension
-                else if self.tt[tt_entry_index].score >= beta as i16 {
+                else if self.tt.probe(self.board.zobrist_hash()).is_some_and(|entry| entry.score >= beta as i16) {
                     extension = -1;
                 }
             }
@@ -482,6 +1024,19 @@ impl Searcher
             alpha = score;
             best_move = mov;
 
+            // Update PV: this move followed by the child's PV
+            if !singular {
+                let ply = ply as usize;
+                let child_pv_length = self.pv_lengths[ply + 1] as usize;
+                self.pv_table[ply][0] = mov;
+
+                for j in 0..child_pv_length {
+                    self.pv_table[ply][j + 1] = self.pv_table[ply + 1][j];
+                }
+
+                self.pv_lengths[ply] = 1 + child_pv_length as u8;
+            }
+
             if ply == 0 { self.best_move_root = mov; }
 
             if score < beta { continue; }
@@ -494,29 +1049,40 @@ impl Searcher
 
             self.killers[ply as usize] = mov; // This move is now a killer move
 
+            if has_previous_move {
+                self.counter_moves[previous_move.from as usize][previous_move.to as usize] = mov;
+            }
+
             // Increase this move's history
             let mut move_history =  &mut self.history[stm][mov.from as usize][mov.to as usize];
             let bonus: i32 = (depth * history_bonus_multiplier()).min(history_bonus_max());
             *move_history += bonus - bonus * *move_history / history_max();
 
+            if has_previous_move {
+                move_history = &mut self.cont_history[cont_history_index(stm, previous_move, mov)];
+                *move_history += bonus - bonus * *move_history / history_max();
+            }
+
             // History malus: decrease history of tried moves
             let malus: i32 = (depth * history_malus_multiplier()).min(history_malus_max());
             for j in 0..i {
                 move_history =  &mut self.history[stm][moves[j].from as usize][moves[j].to as usize];
                 *move_history += -malus - malus * *move_history / history_max();
+
+                if has_previous_move {
+                    move_history = &mut self.cont_history[cont_history_index(stm, previous_move, moves[j])];
+                    *move_history += -malus - malus * *move_history / history_max();
+                }
             }
 
             break;
         }
 
-        // Store in TT
-        if !singular
+        // Store in TT (not at the root if some moves were excluded by MultiPV or searchmoves)
+        if !singular 
+        && !(ply == 0 && (!self.excluded_root_moves.is_empty() || !self.search_moves.is_empty()))
         {
-            let tt_entry: &mut TTEntry = &mut self.tt[tt_entry_index];
-            tt_entry.zobrist_hash = self.board.zobrist_hash();
-            tt_entry.depth = depth as u8;
-
-            tt_entry.score = if best_score >= MIN_WIN_SCORE { 
+            let tt_score: i16 = if best_score >= MIN_WIN_SCORE { 
                 (best_score +  ply as i32) as i16 
             } else if best_score <= -MIN_WIN_SCORE { 
                 (best_score - ply as i32) as i16 
@@ -524,13 +1090,134 @@ impl Searcher
                 best_score as i16 
             };
 
-            if best_move != MOVE_NONE {
-                tt_entry.set_move(best_move);
+            self.store_tt(TTEntry::new(depth as u8, tt_score, best_move, bound));
+        }
+
+        best_score
+    }
+
+    // Quiescence search: only searches moves that capture many enemy pieces, 
+    // which would otherwise make the leaf static eval very volatile
+    fn qsearch(&mut self, ply: u8, qs_ply: u8, mut alpha: i32, beta: i32) -> i32
+    {
+        if self.is_hard_time_up() { return 0; }
+
+        self.pv_lengths[ply as usize] = 0;
+
+        // Update seldepth
+        if ply > self.max_ply_reached {
+            self.max_ply_reached = ply;
+        }
+
+        // Game over?
+        if qs_ply > 0 {
+            if self.board.is_repetition(ply) { return 0; }
+
+            let (game_state, winner): (GameState, Color) = self.board.game_state();
+
+            if game_state == GameState::Draw { return 0 };
+
+            if winner != Color::None {
+                return if winner == self.board.side_to_move() {
+                    INFINITY - (ply as i32)
+                } else {
+                    -INFINITY + (ply as i32)
+                }
             }
+        }
+
+        // Probe TT
+        let tt_probe: Option<TTEntry> = self.tt.probe(self.board.zobrist_hash());
+        let tt_hit: bool = tt_probe.is_some();
+        self.tt_stats.probes += 1;
+        self.tt_stats.hits += tt_hit as u64;
+        let tt_entry: TTEntry = tt_probe.unwrap_or(TTEntry::new(0, 0, MOVE_NONE, Bound::None));
 
-            tt_entry.set_bound(bound);
+        // TT cutoff
+        if tt_hit 
+        && (tt_entry.get_bound() == Bound::Exact
+        || (tt_entry.get_bound() == Bound::Lower && tt_entry.score >= beta as i16)
+        || (tt_entry.get_bound() == Bound::Upper && tt_entry.score <= alpha as i16))
+        {
+            return tt_entry.adjusted_score(ply) as i32;
+        }
+
+        // Stand pat
+        let eval: i32 = evaluate(&mut self.board);
+
+        if eval >= beta || ply >= self.max_depth || qs_ply >= qs_max_plies() { 
+            return eval; 
         }
 
+        let original_alpha: i32 = alpha;
+        if eval > alpha { alpha = eval; }
+
+        // Generate moves and score the high capture ones
+        let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+        let mut moves_scores = ArrayVec::<i32, 256>::new();
+        self.board.moves(&mut moves);
+
+        let mut i: usize = 0;
+        while i < moves.len()
+        {
+            let mov: AtaxxMove = moves[i];
+
+            let captures: u8 = if mov == MOVE_PASS { 0 } else { self.board.num_adjacent_enemies(mov.to) };
+            if captures < qs_min_captures() {
+                moves.swap_remove(i);
+                continue;
+            }
+
+            moves_scores.push(mov.is_single() as i32 * 2 + captures as i32);
+            i += 1;
+        }
+
+        let mut best_score: i32 = eval;
+        let mut best_move: AtaxxMove = MOVE_NONE;
+
+        for i in 0..moves.len()
+        {
+            let (mov, _) = incremental_sort(&mut moves, &mut moves_scores, i);
+
+            self.board.make_move(mov);
+            self.nodes += 1;
+            self.qs_nodes += 1;
+            let score = -self.qsearch(ply + 1, qs_ply + 1, -beta, -alpha);
+            self.board.undo_move();
+
+            if self.is_hard_time_up() { return 0; }
+
+            if score <= best_score { continue; }
+
+            best_score = score;
+
+            if score <= alpha { continue; }
+
+            alpha = score;
+            best_move = mov;
+
+            if score >= beta { break; }
+        }
+
+        // Store in TT
+        let bound: Bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+
+        let tt_score: i16 = if best_score >= MIN_WIN_SCORE { 
+            (best_score + ply as i32) as i16 
+        } else if best_score <= -MIN_WIN_SCORE { 
+            (best_score - ply as i32) as i16 
+        } else { 
+            best_score as i16 
+        };
+
+        self.store_tt(TTEntry::new(0, tt_score, best_move, bound));
+
         best_score
     }
 }
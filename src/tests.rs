@@ -4,6 +4,21 @@ mod tests {
     use crate::ataxx_move::*;
     use crate::board::*;
     use crate::perft::*;
+    use crate::nn::*;
+    use crate::tt_entry::*;
+    use crate::time_manager::*;
+    use crate::utils::*;
+    use crate::uai_command::*;
+    use crate::datagen::*;
+    use crate::training_data::*;
+    use crate::datatool::*;
+    use crate::search::*;
+    use crate::uai::*;
+    use arrayvec::ArrayVec;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
 
     #[test]
     fn test_move_equality() {
@@ -144,4 +159,534 @@ mod tests {
         assert_eq!(board.zobrist_hash(), hash);
     }
 
+    #[test]
+    fn test_repetition()
+    {
+        let mut board = Board::new(START_FEN);
+        let shuffle: [&str; 4] = ["a7c7", "g7e7", "c7a7", "e7g7"];
+
+        for uai_move in shuffle.iter() {
+            assert!(!board.is_repetition(4));
+            board.make_move(AtaxxMove::from_uai(uai_move));
+        }
+
+        // Twofold repetition: a draw inside the search tree but not a game over
+        assert_eq!(board.fen(), "x5o/7/7/7/7/7/o5x x 4 3");
+        assert!(board.is_repetition(4));
+        assert!(!board.is_repetition(3));
+        assert!(!board.is_repetition(0));
+        assert_eq!(board.game_state().0, GameState::Ongoing);
+
+        for uai_move in shuffle.iter() {
+            board.make_move(AtaxxMove::from_uai(uai_move));
+        }
+
+        // Threefold repetition, which game_state() leaves to the caller
+        assert!(board.is_repetition(0));
+        assert_eq!(board.game_state().0, GameState::Ongoing);
+
+        // A single move resets the repetition history
+        board.make_move(AtaxxMove::from_uai("b6"));
+        assert!(!board.is_repetition(255));
+    }
+
+    #[test]
+    fn test_net_header()
+    {
+        let weights: Vec<u8> = vec![7; std::mem::size_of::<Net>()];
+        let header = NetHeader::new(&weights);
+        let bytes = header.to_bytes();
+
+        assert_eq!(&bytes[0..4], b"ZTXN");
+        assert_eq!(NetHeader::from_bytes(&bytes), Ok(NetHeader::new(&weights)));
+        assert!(header.validate(&weights).is_ok());
+
+        // Corrupted weights
+        let mut corrupted_weights = weights.clone();
+        corrupted_weights[1000] = 8;
+        assert!(header.validate(&corrupted_weights).is_err());
+
+        // Different hidden layer size
+        let mut other_header = NetHeader::new(&weights);
+        other_header.hidden_size = 128;
+        assert!(other_header.validate(&weights).is_err());
+
+        // Raw net without header
+        assert!(NetHeader::from_bytes(&weights).is_err());
+    }
+
+    #[test]
+    fn test_accumulator()
+    {
+        let mut board = Board::new("x5o/7/2-1-2/7/2-1-2/7/o5x x 0 1");
+        let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+
+        // Play a game of pseudo random moves, checking that the incrementally updated eval
+        // matches the eval computed from scratch, sometimes evaluating only every few plies
+        for i in 0..200usize 
+        {
+            if board.game_state().0 != GameState::Ongoing { break; }
+
+            if i % 3 != 1 {
+                assert_eq!(evaluate(&mut board), evaluate(&mut Board::new(&board.fen())));
+            }
+
+            board.moves(&mut moves);
+            board.make_move(moves[(i * 7919) % moves.len()]);
+
+            // Undo and redo some moves
+            if i % 5 == 0 {
+                board.undo_move();
+                assert_eq!(evaluate(&mut board), evaluate(&mut Board::new(&board.fen())));
+                board.make_move(moves[(i * 7919) % moves.len()]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tt()
+    {
+        let tt = TT::new(1);
+        let hash: u64 = 0x1234_5678_9abc_def0;
+        let mov = AtaxxMove::double(3, 5);
+        assert!(tt.probe(hash).is_none());
+
+        tt.store(hash, TTEntry::new(5, -300, mov, Bound::Lower));
+        let tt_entry = tt.probe(hash).unwrap();
+        assert!(tt_entry.depth == 5 && tt_entry.score == -300);
+        assert!(tt_entry.get_move() == mov && tt_entry.get_bound() == Bound::Lower);
+
+        // Same bucket, different key
+        assert!(tt.probe(hash ^ 1).is_none());
+
+        // Storing without a move keeps the old one
+        tt.store(hash, TTEntry::new(6, 20, MOVE_NONE, Bound::Upper));
+        let tt_entry = tt.probe(hash).unwrap();
+        assert!(tt_entry.depth == 6 && tt_entry.get_move() == mov && tt_entry.get_bound() == Bound::Upper);
+
+        // A much shallower entry of the same position only replaces it if exact or if the old one is from a previous search
+        tt.store(hash, TTEntry::new(0, 50, MOVE_NONE, Bound::Lower));
+        assert!(tt.probe(hash).unwrap().depth == 6);
+        tt.store(hash, TTEntry::new(3, 50, MOVE_NONE, Bound::Lower));
+        assert!(tt.probe(hash).unwrap().depth == 3);
+        tt.store(hash, TTEntry::new(6, 20, MOVE_NONE, Bound::Upper));
+        tt.store(hash, TTEntry::new(0, 40, MOVE_NONE, Bound::Exact));
+        assert!(tt.probe(hash).unwrap().score == 40);
+        tt.store(hash, TTEntry::new(6, 20, MOVE_NONE, Bound::Upper));
+        tt.increment_age();
+        tt.store(hash, TTEntry::new(0, 60, MOVE_NONE, Bound::Lower));
+        assert!(tt.probe(hash).unwrap().score == 60);
+
+        // A full bucket replaces the shallowest entry
+        for i in 1..=TT_BUCKET_SIZE as u64 {
+            tt.store(hash ^ i, TTEntry::new(10 + i as u8, 0, MOVE_NONE, Bound::Exact));
+        }
+        assert!(tt.probe(hash).is_none());
+        assert!(tt.probe(hash ^ 1).is_some());
+
+        tt.clear();
+        assert!(tt.probe(hash ^ 1).is_none());
+    }
+
+    #[test]
+    fn test_time_manager_sudden_death()
+    {
+        let tm = TimeManager::new(10_000, 0, None, 10);
+        assert_eq!(tm.hard_milliseconds(), 4995);
+        assert!(tm.soft_milliseconds() > 250 && tm.soft_milliseconds() < 350);
+
+        // Less time left, less time used
+        let short_tm = TimeManager::new(1000, 0, None, 10);
+        assert!(short_tm.hard_milliseconds() < tm.hard_milliseconds());
+        assert!(short_tm.soft_milliseconds() < tm.soft_milliseconds());
+
+        // Move overhead bigger than the time left
+        let flagging_tm = TimeManager::new(5, 0, None, 10);
+        assert_eq!(flagging_tm.hard_milliseconds(), 0);
+        assert_eq!(flagging_tm.soft_milliseconds(), 0);
+    }
+
+    #[test]
+    fn test_time_manager_increment()
+    {
+        let tm = TimeManager::new(10_000, 0, None, 10);
+        let inc_tm = TimeManager::new(10_000, 100, None, 10);
+        assert_eq!(inc_tm.hard_milliseconds(), tm.hard_milliseconds());
+        assert!(inc_tm.soft_milliseconds() > tm.soft_milliseconds());
+        assert!(inc_tm.soft_milliseconds() <= inc_tm.hard_milliseconds());
+
+        // A huge increment can't make the soft limit exceed the hard one
+        let huge_inc_tm = TimeManager::new(1000, 100_000, None, 10);
+        assert_eq!(huge_inc_tm.soft_milliseconds(), huge_inc_tm.hard_milliseconds());
+    }
+
+    #[test]
+    fn test_time_manager_moves_to_go()
+    {
+        let tm = TimeManager::new(10_000, 0, Some(40), 10);
+        assert_eq!(tm.hard_milliseconds(), 749);
+        assert_eq!(tm.soft_milliseconds(), 149);
+
+        // Fewer moves to go, more time per move
+        let tm_10 = TimeManager::new(10_000, 0, Some(10), 10);
+        assert!(tm_10.soft_milliseconds() > tm.soft_milliseconds());
+        assert!(tm_10.hard_milliseconds() <= 4995);
+
+        // Last move before the time control can use all the time left
+        let tm_1 = TimeManager::new(10_000, 0, Some(1), 10);
+        assert_eq!(tm_1.hard_milliseconds(), 9990);
+        assert!(tm_1.soft_milliseconds() < tm_1.hard_milliseconds());
+        assert_eq!(TimeManager::new(10_000, 0, Some(0), 10), tm_1);
+    }
+
+    #[test]
+    fn test_time_manager_move_time()
+    {
+        let tm = TimeManager::move_time(1000, 10);
+        assert_eq!(tm.hard_milliseconds(), 990);
+        assert_eq!(tm.soft_milliseconds(), U64_MAX);
+        assert_eq!(tm.scaled_soft_milliseconds(0.5), U64_MAX);
+        assert_eq!(TimeManager::move_time(5, 10).hard_milliseconds(), 0);
+
+        let mut infinite_tm = TimeManager::infinite();
+        infinite_tm.delay(1000);
+        assert_eq!(infinite_tm, TimeManager::infinite());
+
+        let mut delayed_tm = TimeManager::move_time(1000, 10);
+        delayed_tm.delay(500);
+        assert_eq!(delayed_tm.hard_milliseconds(), 1490);
+    }
+
+    #[test]
+    fn test_score_reporting()
+    {
+        assert_eq!(score_to_string(35), "cp 35");
+        assert_eq!(score_to_string(-MIN_WIN_SCORE + 1), format!("cp {}", -MIN_WIN_SCORE + 1));
+        assert_eq!(score_to_string(INFINITY - 1), "mate 1");
+        assert_eq!(score_to_string(INFINITY - 5), "mate 3");
+        assert_eq!(score_to_string(-INFINITY + 2), "mate -1");
+        assert_eq!(score_to_string(-INFINITY + 6), "mate -3");
+
+        let (win, draw, loss) = wdl_model(0);
+        assert!(win == loss && win + draw + loss == 1000);
+
+        let (win, draw, loss) = wdl_model(300);
+        assert!(win > loss && win + draw + loss == 1000);
+
+        assert_eq!(wdl_model(INFINITY - 3), (1000, 0, 0));
+        assert_eq!(wdl_model(-INFINITY + 3), (0, 0, 1000));
+    }
+
+    #[test]
+    fn test_uai_command_parsing()
+    {
+        assert_eq!(parse_command("isready"), Ok(UaiCommand::IsReady));
+        assert_eq!(parse_command("perft 5"), Ok(UaiCommand::Perft(5)));
+        assert_eq!(parse_command("bench"), Ok(UaiCommand::Bench(None)));
+
+        assert_eq!(parse_command("setoption name Skill Level value 3"), 
+                   Ok(UaiCommand::SetOption { name: "skill level".to_string(), value: "3".to_string() }));
+
+        assert_eq!(parse_command("setoption name EvalFile value my nets/net.bin"), 
+                   Ok(UaiCommand::SetOption { name: "evalfile".to_string(), value: "my nets/net.bin".to_string() }));
+
+        assert_eq!(parse_command("position startpos moves g2 0000 a1c3"), Ok(UaiCommand::Position { 
+            fen: None, 
+            moves: vec![AtaxxMove::from_uai("g2"), MOVE_PASS, AtaxxMove::from_uai("a1c3")]
+        }));
+
+        assert_eq!(parse_command("position fen x5o/7/7/7/7/7/o5x o 0 1"), Ok(UaiCommand::Position { 
+            fen: Some("x5o/7/7/7/7/7/o5x o 0 1".to_string()), 
+            moves: Vec::new()
+        }));
+
+        // wtime is blue's time, btime is red's, unless rtime is used
+        let Ok(UaiCommand::Go(params)) = parse_command("go wtime 1000 btime 2000 winc 10 binc 20 movestogo 5") 
+        else { panic!() };
+        assert_eq!((params.time(Color::Blue), params.time(Color::Red)), (Some(1000), Some(2000)));
+        assert_eq!((params.inc(Color::Blue), params.inc(Color::Red)), (Some(10), Some(20)));
+        assert_eq!((params.moves_to_go, params.depth), (Some(5), None));
+
+        let Ok(UaiCommand::Go(params)) = parse_command("go rtime 1000 btime 2000 searchmoves b6 f2 depth 300 ponder") 
+        else { panic!() };
+        assert_eq!((params.time(Color::Red), params.time(Color::Blue)), (Some(1000), Some(2000)));
+        assert_eq!(params.search_moves, vec![AtaxxMove::from_uai("b6"), AtaxxMove::from_uai("f2")]);
+        assert_eq!(params.depth, Some(255));
+        assert!(params.ponder);
+
+        // At least 1, the search must complete an iteration
+        let Ok(UaiCommand::Go(params)) = parse_command("go depth 0") else { panic!() };
+        assert_eq!(params.depth, Some(1));
+        assert_eq!(parse_command("bench 0"), Ok(UaiCommand::Bench(Some(1))));
+
+        // Unknown parameters are ignored with their values
+        let Ok(UaiCommand::Go(params)) = parse_command("go mate 3 movetime 100 foo") else { panic!() };
+        assert_eq!(params.move_time, Some(100));
+        assert_eq!(params.ignored, vec!["mate 3".to_string(), "foo".to_string()]);
+    }
+
+    #[test]
+    fn test_uai_command_parsing_malformed()
+    {
+        let malformed: [&str; 18] = [
+            "",
+            "   ",
+            "foo",
+            "perft",
+            "perft -1",
+            "perftsplit abc",
+            "bench 999",
+            "makemove",
+            "makemove a8",
+            "makemove a1é3",
+            "setoption",
+            "setoption name",
+            "setoption hash value 32",
+            "position",
+            "position fen",
+            "position startpos moves h1",
+            "go depth",
+            "go movetime abc",
+        ];
+
+        for line in malformed {
+            assert!(parse_command(line).is_err(), "{}", line);
+        }
+
+        assert!(AtaxxMove::try_from_uai("a1g7").is_some());
+        assert!(AtaxxMove::try_from_uai("a1g").is_none());
+        assert_eq!(AtaxxMove::try_from_uai("0000"), Some(MOVE_PASS));
+    }
+
+    #[test]
+    fn test_fen_errors()
+    {
+        let fen_errors: [(&str, FenError); 12] = [
+            ("", FenError::MissingField("pieces")),
+            ("x5o/7/7/7/7/7/o5x", FenError::MissingField("side to move")),
+            ("x5o/7/7/7/7/7/o5x x 0 1 extra", FenError::TooManyFields(5)),
+            ("x5o/7/7/7/7/7/o5x y 0 1", FenError::SideToMove("y".to_string())),
+            ("x5o/7/7/7/7/7/o5x x -3 1", FenError::HalfmoveClock("-3".to_string())),
+            ("x5o/7/7/7/7/7/o5x x 0 abc", FenError::FullmoveCounter("abc".to_string())),
+            ("x5o/7/7/7/7/7/o5x x 0 -1", FenError::FullmoveCounter("-1".to_string())),
+            ("x5o/7/7/7/7/o5x x 0 1", FenError::RowCount(6)),
+            ("x6o/7/7/7/7/7/o5x x 0 1", FenError::RowLength { rank: 7, length: 8 }),
+            ("x5o/7/7/7/2-1-/7/o5x x 0 1", FenError::RowLength { rank: 3, length: 5 }),
+            ("x5o/7/7/7/7/7/o4-xx x 0 1", FenError::RowLength { rank: 1, length: 8 }),
+            ("x5o/7/7/3z3/7/7/o5x x 0 1", FenError::UnknownChar('z')),
+        ];
+
+        for (fen, error) in fen_errors {
+            assert_eq!(Board::from_fen(fen).err(), Some(error), "{}", fen);
+        }
+
+        // Counters are optional
+        let board = Board::from_fen("x5o/7/2-1-2/7/2-1-2/7/o5x o").unwrap();
+        assert_eq!(board.fen(), "x5o/7/2-1-2/7/2-1-2/7/o5x o 0 1");
+    }
+
+    #[test]
+    fn test_datagen_args()
+    {
+        let args = |line: &str| -> Vec<String> { line.split_whitespace().map(str::to_string).collect() };
+
+        let config = DatagenConfig::from_args(&args("--soft-nodes 100 --hard-nodes 200 --output out.txt --games 3")).unwrap();
+        assert!(config.soft_nodes == 100 && config.hard_nodes == 200 && config.output == "out.txt");
+        assert!(config.max_games == Some(3) && config.max_positions.is_none());
+        assert!(config.min_plies == DatagenConfig::default().min_plies);
+
+        assert!(DatagenConfig::from_args(&args("--soft-nodes")).is_err());
+        assert!(DatagenConfig::from_args(&args("--soft-nodes abc")).is_err());
+        assert!(DatagenConfig::from_args(&args("--bogus 1")).is_err());
+        assert!(DatagenConfig::from_args(&args("soft-nodes 1")).is_err());
+        assert!(DatagenConfig::from_args(&args("--min-plies 9 --max-plies 8")).is_err());
+
+        assert_eq!(DatagenConfig::from_args(&args("--seed 7")).unwrap().seed, 7);
+
+        // Resuming loads the saved options, of which only the threads and limits can be changed.
+        // The file name is unique to this process, so concurrent test runs don't share it.
+        let file_name: String = format!("zataxx_test_resume_{}.bin", std::process::id());
+        let output: String = std::env::temp_dir().join(file_name).to_str().unwrap().to_string();
+        let saved = DatagenConfig::from_args(&args(&format!("--soft-nodes 100 --hard-nodes 200 --seed 5 --output {}", output))).unwrap();
+        let progress = DatagenProgress { next_game: 4, games: 3, positions: 2, bytes: DataEntry::SIZE as u64 * 2 };
+        std::fs::write(&output, [0u8; DataEntry::SIZE * 2]).unwrap();
+        std::fs::write(metadata_path(&output), saved.metadata(&progress)).unwrap();
+
+        let resumed = DatagenConfig::from_args(&args(&format!("--resume {} --threads 3 --games 10", output))).unwrap();
+        assert!(resumed.seed == 5 && resumed.soft_nodes == 100 && resumed.threads == 3);
+        assert!(resumed.format == DataFormat::Binary && resumed.output == output);
+        assert_eq!(resumed.max_games, Some(10));
+        assert_eq!(resumed.resume, Some(progress));
+
+        for option in ["--seed 6", "--format text", "--min-plies 2", "--output other.bin"] {
+            assert!(DatagenConfig::from_args(&args(&format!("--resume {} {}", output, option))).is_err(), "{}", option);
+        }
+
+        // The output is shorter than what was written
+        std::fs::write(&output, [0u8; DataEntry::SIZE]).unwrap();
+        assert!(DatagenConfig::from_args(&args(&format!("--resume {}", output))).is_err());
+
+        let _ = std::fs::remove_file(metadata_path(&output));
+        let _ = std::fs::remove_file(&output);
+        assert!(DatagenConfig::from_args(&args(&format!("--resume {}", output))).is_err());
+
+        assert_eq!(OpeningsConfig::from_args(&args("--plies 6 --openings 10")).unwrap().max_openings, Some(10));
+        assert!(OpeningsConfig::from_args(&args("--plies 0")).is_err());
+    }
+
+    #[test]
+    fn test_training_data_round_trip()
+    {
+        let lines: [&str; 3] = [
+            "x5o/7/2-1-2/7/2-1-2/7/o5x x 0 1 | f2 | 35 | 0.5",
+            "4x1o/4x2/1xxx1o1/1x3o1/4o2/5o1/2o1o1o o 17 1 | e3c4 | -214 | 1.0",
+            "7/7/7/7/-------/-------/x5o o 3 1 | 0000 | 8000 | 0.0",
+        ];
+
+        let entries: Vec<DataEntry> = lines.iter()
+            .map(|line| DataEntry::from_text_line(line).unwrap())
+            .collect();
+
+        // The binary format has no best move
+        let entries_without_moves: Vec<DataEntry> = entries.iter()
+            .map(|entry| DataEntry { best_move: MOVE_NONE, ..*entry })
+            .collect();
+
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(entries[i].to_text_line(), *line);
+            assert_eq!(DataEntry::from_bytes(&entries[i].to_bytes()).unwrap(), entries_without_moves[i]);
+        }
+
+        assert_eq!(entries_without_moves[2].to_text_line(), "7/7/7/7/-------/-------/x5o o 3 1 | none | 8000 | 0.0");
+        assert_eq!(DataEntry::from_text_line(&entries_without_moves[2].to_text_line()).unwrap(), entries_without_moves[2]);
+
+        for format in [DataFormat::Text, DataFormat::Binary]
+        {
+            let mut writer = DataWriter::new(Vec::<u8>::new(), format);
+            for entry in entries.iter() {
+                writer.write(entry).unwrap();
+            }
+
+            let bytes: Vec<u8> = writer.get_ref().clone();
+            if format == DataFormat::Binary {
+                assert_eq!(bytes.len(), entries.len() * DataEntry::SIZE);
+            }
+
+            let read_entries: Vec<DataEntry> = DataReader::new(&bytes[..], format)
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(read_entries, if format == DataFormat::Binary { entries_without_moves.clone() } else { entries.clone() });
+
+            // A truncated binary file is an error
+            if format == DataFormat::Binary {
+                let mut reader = DataReader::new(&bytes[..bytes.len() - 1], format);
+                assert!(reader.nth(2).unwrap().is_err());
+            }
+        }
+
+        assert!(DataEntry::from_text_line("x5o/7/7/7/7/7/o5x x 0 1 | f2 | 35").is_err());
+        assert!(DataEntry::from_text_line("x5o/7/7/7/7/7/o5x x 0 1 | f2 | 35 | 2.0").is_err());
+        assert!(DataEntry::from_bytes(&[0xff; DataEntry::SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_training_data_binary_layout()
+    {
+        // bulletformat's AtaxxBoard: stm relative bitboards, score and result, then stm, fullmove and halfmove
+        let entry = DataEntry::from_text_line("x5o/7/2-1-2/7/2-1-2/7/o5x o 7 12 | f2 | 35 | 1.0").unwrap();
+        let bytes: [u8; DataEntry::SIZE] = entry.to_bytes();
+
+        assert_eq!(bytes[0..8], entry.blue.to_le_bytes());
+        assert_eq!(bytes[8..16], entry.red.to_le_bytes());
+        assert_eq!(bytes[16..24], entry.gaps.to_le_bytes());
+        assert_eq!(bytes[24..26], (-35i16).to_le_bytes());
+        assert_eq!(bytes[26], 0); // blue lost
+        assert_eq!(bytes[27], 1); // blue to move
+        assert_eq!(bytes[28..30], 12u16.to_le_bytes());
+        assert_eq!(bytes[30], 7);
+        assert_eq!(bytes[31], 0);
+
+        // Red to move is stored as is
+        let entry = DataEntry::from_text_line("x5o/7/2-1-2/7/2-1-2/7/o5x x 0 1 | f2 | 35 | 1.0").unwrap();
+        let bytes: [u8; DataEntry::SIZE] = entry.to_bytes();
+        assert_eq!(bytes[0..8], entry.red.to_le_bytes());
+        assert_eq!((bytes[24..26].to_vec(), bytes[26], bytes[27]), (35i16.to_le_bytes().to_vec(), 2, 0));
+    }
+
+    #[test]
+    fn test_datatool_filter_and_stats()
+    {
+        // Two games of 2 and 1 positions
+        let lines: [&str; 3] = [
+            "x5o/7/7/7/7/7/o5x x 0 1 | f2 | 35 | 0.5",
+            "x5o/7/7/7/7/5x1/o5x o 0 1 | a2 | -1500 | 0.5",
+            "4x1o/4x2/1xxx1o1/1x3o1/4o2/5o1/2o1o1o o 17 1 | e3c4 | 250 | 1.0",
+        ];
+
+        let entries: Vec<DataEntry> = lines.iter()
+            .map(|line| DataEntry::from_text_line(line).unwrap())
+            .collect();
+
+        let args: Vec<String> = ["--max-score", "300", "--min-pieces", "5"].iter().map(|arg| arg.to_string()).collect();
+        let filter = DataFilter::from_args(&args).unwrap();
+        let accepted: Vec<bool> = entries.iter().map(|entry| filter.accepts(entry)).collect();
+        assert_eq!(accepted, [false, false, true]);
+
+        assert!(DataFilter::from_args(&["--max-pieces".to_string()]).is_err());
+
+        let mut stats = DataStats::default();
+        for entry in entries.iter() {
+            stats.add(entry);
+        }
+        stats.finish();
+
+        assert_eq!(stats.positions, 3);
+        assert_eq!(stats.results, [0, 2, 1]);
+        assert_eq!(stats.game_lengths, [2, 1]);
+
+        // Binary data has no best moves, so games are found by trying all moves
+        let mut stats = DataStats::default();
+        for entry in entries.iter() {
+            stats.add(&DataEntry { best_move: MOVE_NONE, ..*entry });
+        }
+        stats.finish();
+        assert_eq!(stats.game_lengths, [2, 1]);
+        assert_eq!(stats.score_histogram.first(), Some(&1));
+        assert_eq!(stats.score_histogram.iter().sum::<u64>(), 3);
+
+        // The same position with the other side to move is a different position
+        let mut other_stm: DataEntry = entries[0];
+        other_stm.stm = Color::Blue;
+        assert!(other_stm.board_state().zobrist_hash != entries[0].board_state().zobrist_hash);
+        assert_eq!(entries[0].board_state().zobrist_hash, Board::new(lines[0].split('|').next().unwrap()).zobrist_hash());
+    }
+
+    #[test]
+    fn test_search_after_stopped_ponder()
+    {
+        let searcher_mutex = Arc::new(Mutex::new(Searcher::new(Board::new(START_FEN))));
+        let stop = searcher_mutex.lock().unwrap().stop_flag();
+        let ponder = searcher_mutex.lock().unwrap().ponder_flag();
+
+        // go ponder, then stop before the ponderhit
+        let params = GoParams { ponder: true, depth: Some(2), ..GoParams::default() };
+        let mut search_thread = Some(go(params, Arc::clone(&searcher_mutex)));
+        stop.store(true, Ordering::Relaxed);
+        wait_for_search(&mut search_thread, &stop, &ponder, &searcher_mutex);
+
+        // A later search that doesn't go through go() (e.g. 'display') isn't pondering, so it returns
+        let (sender, receiver) = mpsc::channel::<AtaxxMove>();
+        let searcher_mutex_clone = Arc::clone(&searcher_mutex);
+        thread::Builder::new()
+            .stack_size(SEARCH_THREAD_STACK_SIZE)
+            .spawn(move || {
+                let mut searcher = searcher_mutex_clone.lock().unwrap();
+                let best_move = searcher.search(3, TimeManager::infinite(), U64_MAX, 10_000, false).0;
+                let _ = sender.send(best_move);
+            })
+            .unwrap();
+
+        let best_move = receiver.recv_timeout(Duration::from_secs(10)).expect("search didn't return");
+        assert!(best_move != MOVE_NONE);
+    }
 }
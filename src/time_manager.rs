@@ -0,0 +1,89 @@
+use crate::types::*;
+use crate::search::*;
+
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 10;
+pub const MAX_MOVE_OVERHEAD: u64 = 5000;
+
+// Soft and hard time limits of a search, in milliseconds since the search started.
+// The search stops at the hard limit, and doesn't start another iteration after the soft one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeManager {
+    hard_milliseconds: u64,
+    soft_milliseconds: u64,
+}
+
+impl TimeManager
+{
+    pub fn infinite() -> Self {
+        Self {
+            hard_milliseconds: U64_MAX,
+            soft_milliseconds: U64_MAX
+        }
+    }
+
+    // 'go movetime', use all of it
+    pub fn move_time(milliseconds: u64, move_overhead: u64) -> Self {
+        Self {
+            hard_milliseconds: milliseconds.saturating_sub(move_overhead),
+            soft_milliseconds: U64_MAX
+        }
+    }
+
+    // Sudden death (with or without increment) if moves_to_go is None,
+    // otherwise the remaining time must last for the next moves_to_go moves
+    pub fn new(milliseconds: i64, increment_ms: u64, moves_to_go: Option<u64>, move_overhead: u64) -> Self
+    {
+        let max_hard_ms: u64 = (milliseconds - move_overhead as i64).max(0) as u64;
+        let increment_share: f64 = increment_ms as f64 * 0.6666;
+
+        let (hard_milliseconds, soft_milliseconds) = match moves_to_go
+        {
+            None => {
+                let hard: f64 = max_hard_ms as f64 * hard_time_percentage();
+                let soft: f64 = (max_hard_ms as f64 * soft_time_percentage() + increment_share) * soft_time_scale();
+                (hard, soft)
+            }
+            Some(moves_to_go) => {
+                // With 1 move to go, the whole remaining time can be used
+                let moves_to_go: u64 = moves_to_go.max(1);
+                let max_hard_fraction: f64 = if moves_to_go == 1 { 1.0 } else { hard_time_percentage() };
+                let base: f64 = max_hard_ms as f64 / moves_to_go as f64 + increment_share;
+
+                let hard: f64 = (base * movestogo_hard_scale()).min(max_hard_ms as f64 * max_hard_fraction);
+                let soft: f64 = base * soft_time_scale();
+                (hard, soft)
+            }
+        };
+
+        let hard_milliseconds = hard_milliseconds as u64;
+
+        Self {
+            hard_milliseconds,
+            soft_milliseconds: (soft_milliseconds as u64).min(hard_milliseconds)
+        }
+    }
+
+    pub fn hard_milliseconds(&self) -> u64 {
+        self.hard_milliseconds
+    }
+
+    pub fn soft_milliseconds(&self) -> u64 {
+        self.soft_milliseconds
+    }
+
+    // Soft limit multiplied by a search-dependent factor (e.g. nodes TM), never above the hard limit
+    pub fn scaled_soft_milliseconds(&self, scale: f64) -> u64
+    {
+        if self.soft_milliseconds == U64_MAX {
+            return U64_MAX;
+        }
+
+        ((self.soft_milliseconds as f64 * scale) as u64).min(self.hard_milliseconds)
+    }
+
+    // Both limits start counting later, e.g. on ponderhit
+    pub fn delay(&mut self, milliseconds: u64) {
+        self.hard_milliseconds = self.hard_milliseconds.saturating_add(milliseconds);
+        self.soft_milliseconds = self.soft_milliseconds.saturating_add(milliseconds);
+    }
+}
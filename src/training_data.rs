@@ -0,0 +1,278 @@
+use std::io::{self, BufRead, Write};
+use crate::types::*;
+use crate::utils::*;
+use crate::ataxx_move::*;
+use crate::board::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DataFormat {
+    Text, // <fen> | <move> | <score> | <result> lines
+    Binary // DataEntry::SIZE bytes per position, bullet's AtaxxBoard layout (without the best move)
+}
+
+impl DataFormat
+{
+    // Files ending in .bin are binary, anything else is text
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".bin") { DataFormat::Binary } else { DataFormat::Text }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DataFormat::Text => "txt",
+            DataFormat::Binary => "bin"
+        }
+    }
+}
+
+// A training position. Score and result are from red's point of view.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DataEntry {
+    pub red: u64,
+    pub blue: u64,
+    pub gaps: u64,
+    pub stm: Color,
+    pub plies_since_single: u8,
+    pub move_counter: u16, // fullmove counter
+    pub score: i16,
+    pub result: u8, // 0 blue won, 1 draw, 2 red won
+    pub best_move: AtaxxMove, // MOVE_NONE if unknown, e.g. read from binary data
+}
+
+impl DataEntry
+{
+    // Same layout as bulletformat's AtaxxBoard, so bullet can train on it directly. Little endian:
+    // side to move's pieces (8 bytes), opponent's pieces (8), gaps (8), score (2), result (1), 
+    // stm (1, 0 red, 1 blue), fullmove counter (2), plies since single (1), unused (1).
+    // Score and result are from the side to move's pov, result being 0 loss, 1 draw, 2 win.
+    // The best move doesn't fit, so it's only in the text format.
+    pub const SIZE: usize = 32;
+
+    pub fn new(board: &Board, score: i16, result: u8, best_move: AtaxxMove) -> Self {
+        Self {
+            red: board.red(),
+            blue: board.blue(),
+            gaps: board.gaps(),
+            stm: board.side_to_move(),
+            plies_since_single: board.plies_since_single().min(u8::MAX as u16) as u8,
+            move_counter: board.move_counter(),
+            score,
+            result,
+            best_move
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE]
+    {
+        let (us, them, score, result) = if self.stm == Color::Red {
+            (self.red, self.blue, self.score, self.result)
+        } else {
+            (self.blue, self.red, self.score.saturating_neg(), 2 - self.result)
+        };
+
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..8].copy_from_slice(&us.to_le_bytes());
+        bytes[8..16].copy_from_slice(&them.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.gaps.to_le_bytes());
+        bytes[24..26].copy_from_slice(&score.to_le_bytes());
+        bytes[26] = result;
+        bytes[27] = self.stm as u8;
+        bytes[28..30].copy_from_slice(&self.move_counter.to_le_bytes());
+        bytes[30] = self.plies_since_single;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Result<Self, String>
+    {
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        const ALL_SQUARES: u64 = (1u64 << 49) - 1;
+
+        let mut entry = Self {
+            red: u64_at(0),
+            blue: u64_at(8),
+            gaps: u64_at(16),
+            score: i16::from_le_bytes([bytes[24], bytes[25]]),
+            result: bytes[26],
+            stm: match bytes[27] {
+                0 => Color::Red,
+                1 => Color::Blue,
+                stm => return Err(format!("Invalid side to move {}", stm))
+            },
+            move_counter: u16::from_le_bytes([bytes[28], bytes[29]]),
+            plies_since_single: bytes[30],
+            best_move: MOVE_NONE
+        };
+
+        if (entry.red | entry.blue | entry.gaps) & !ALL_SQUARES != 0
+        || entry.red & entry.blue != 0
+        || (entry.red | entry.blue) & entry.gaps != 0
+        {
+            return Err("Invalid bitboards".to_string());
+        }
+
+        if entry.result > 2 {
+            return Err(format!("Invalid result {}", entry.result));
+        }
+
+        // From the side to move's pov to red's
+        if entry.stm == Color::Blue {
+            (entry.red, entry.blue) = (entry.blue, entry.red);
+            entry.score = entry.score.saturating_neg();
+            entry.result = 2 - entry.result;
+        }
+
+        Ok(entry)
+    }
+
+    pub fn board_state(&self) -> BoardState
+    {
+        let mut board_state = BoardState::default();
+        board_state.color = self.stm;
+        board_state.gaps = self.gaps;
+        board_state.plies_since_single = self.plies_since_single as u16;
+        board_state.move_counter = self.move_counter;
+
+        for (color, mut bitboard) in [(Color::Red, self.red), (Color::Blue, self.blue)] {
+            while bitboard > 0 {
+                board_state.place_piece(color, pop_lsb(&mut bitboard) as Square);
+            }
+        }
+
+        board_state.zobrist_hash ^= ZOBRIST_COLOR[self.stm as usize];
+        board_state
+    }
+
+    pub fn num_pieces(&self) -> u32 {
+        (self.red | self.blue).count_ones()
+    }
+
+    pub fn fen(&self) -> String {
+        self.board_state().fen()
+    }
+
+    // <fen> | <move> | <score> | <result>, the move being 'none' if unknown
+    pub fn to_text_line(self) -> String
+    {
+        let result: &str = match self.result {
+            2 => "1.0",
+            1 => "0.5",
+            _ => "0.0"
+        };
+
+        let best_move: String = if self.best_move == MOVE_NONE { "none".to_string() } else { self.best_move.to_string() };
+        format!("{} | {} | {} | {}", self.fen(), best_move, self.score, result)
+    }
+
+    pub fn from_text_line(line: &str) -> Result<Self, String>
+    {
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        if fields.len() != 4 {
+            return Err(format!("Expected 4 fields separated by | but got {}", fields.len()));
+        }
+
+        let board = Board::from_fen(fields[0]).map_err(|error| format!("Invalid fen {}: {}", fields[0], error))?;
+
+        let best_move: AtaxxMove = if fields[1] == "none" {
+            MOVE_NONE
+        } else {
+            AtaxxMove::try_from_uai(fields[1]).ok_or(format!("Invalid move {}", fields[1]))?
+        };
+
+        let score: i16 = fields[2].parse().map_err(|_| format!("Invalid score {}", fields[2]))?;
+
+        let result: u8 = match fields[3] {
+            "1.0" | "1" => 2,
+            "0.5" => 1,
+            "0.0" | "0" => 0,
+            result => return Err(format!("Invalid result {}", result))
+        };
+
+        Ok(Self::new(&board, score, result, best_move))
+    }
+}
+
+pub struct DataWriter<W: Write> {
+    writer: W,
+    format: DataFormat
+}
+
+impl<W: Write> DataWriter<W>
+{
+    pub fn new(writer: W, format: DataFormat) -> Self {
+        Self { writer, format }
+    }
+
+    pub fn write(&mut self, entry: &DataEntry) -> io::Result<()>
+    {
+        match self.format {
+            DataFormat::Text => writeln!(self.writer, "{}", entry.to_text_line()),
+            DataFormat::Binary => self.writer.write_all(&entry.to_bytes())
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+}
+
+pub struct DataReader<R: BufRead> {
+    reader: R,
+    format: DataFormat,
+    line: String
+}
+
+impl<R: BufRead> DataReader<R>
+{
+    pub fn new(reader: R, format: DataFormat) -> Self {
+        Self { reader, format, line: String::new() }
+    }
+
+    // None at the end of the data. A binary file with a partial entry at the end is an error.
+    pub fn read(&mut self) -> io::Result<Option<DataEntry>>
+    {
+        let invalid_data = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+
+        match self.format {
+            DataFormat::Text => {
+                loop {
+                    self.line.clear();
+                    if self.reader.read_line(&mut self.line)? == 0 {
+                        return Ok(None);
+                    }
+
+                    // Skip empty lines
+                    if !self.line.trim().is_empty() {
+                        return DataEntry::from_text_line(self.line.trim()).map(Some).map_err(invalid_data);
+                    }
+                }
+            }
+            DataFormat::Binary => {
+                let mut bytes = [0u8; DataEntry::SIZE];
+                let mut bytes_read: usize = 0;
+
+                while bytes_read < DataEntry::SIZE {
+                    match self.reader.read(&mut bytes[bytes_read..])? {
+                        0 if bytes_read == 0 => return Ok(None),
+                        0 => return Err(invalid_data(format!("Truncated entry of {} bytes", bytes_read))),
+                        n => bytes_read += n
+                    }
+                }
+
+                DataEntry::from_bytes(&bytes).map(Some).map_err(invalid_data)
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for DataReader<R>
+{
+    type Item = io::Result<DataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
@@ -1,4 +1,5 @@
 use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use crate::types::*;
 use crate::ataxx_move::*;
 
@@ -13,31 +14,32 @@ pub enum Bound {
 }
 
 #[derive(Clone, Copy)]
-#[repr(packed)]
 pub struct TTEntry {
-    pub zobrist_hash: u64,
+    pub key: u16, // lowest 16 bits of the zobrist hash
     pub depth: u8,
     pub score: i16,
     move_and_bound: u16, // lowest 12 bits move, highest 2 bits bound
+    pub age: u8,
 }
 
 impl TTEntry
 {
-    pub fn default() -> Self {
+    pub fn new(depth: u8, score: i16, mov: AtaxxMove, bound: Bound) -> Self {
         Self {
-            zobrist_hash: 0,
-            depth: 0,
-            score: 0,
-            move_and_bound: 0
+            key: 0,
+            depth,
+            score,
+            move_and_bound: mov.to_u12() | ((bound as u16) << 14),
+            age: 0
         }
     }
 
     pub fn adjusted_score(&self, ply: u8) -> i16
     {
-        if self.score >= MIN_WIN_SCORE as i16 { 
+        if self.score >= MIN_WIN_SCORE as i16 {
             return self.score - ply as i16;
         }
-        if self.score <= -MIN_WIN_SCORE as i16 { 
+        if self.score <= -MIN_WIN_SCORE as i16 {
             return self.score + ply as i16;
         }
         self.score
@@ -49,7 +51,7 @@ impl TTEntry
 
     pub fn get_bound(&self) -> Bound {
         let bound: Bound = unsafe { mem::transmute((self.move_and_bound >> 14) as u8) };
-        bound 
+        bound
     }
 
     pub fn set_move(&mut self, mov: AtaxxMove) {
@@ -57,13 +59,222 @@ impl TTEntry
         self.move_and_bound |= mov.to_u12();
     }
 
+    #[allow(dead_code)]
     pub fn set_bound(&mut self, bound: Bound) {
         self.move_and_bound &= 0b0000_1111_1111_1111;
         self.move_and_bound |= (bound as u16) << 14;
     }
 
-    #[allow(dead_code)]
-    pub fn set_move_and_bound(&mut self, mov: AtaxxMove, bound: Bound) {
-        self.move_and_bound = mov.to_u12() | ((bound as u16) << 14);
+    // Bits 0-15 key, 16-31 score, 32-39 depth, 40-55 move and bound, 56-61 age
+    fn pack(&self) -> u64 {
+        self.key as u64
+        | ((self.score as u16 as u64) << 16)
+        | ((self.depth as u64) << 32)
+        | ((self.move_and_bound as u64) << 40)
+        | ((self.age as u64) << 56)
+    }
+
+    fn with_key_and_age(mut self, key: u16, age: u8) -> Self {
+        self.key = key;
+        self.age = age;
+        self
+    }
+
+    fn unpack(data: u64) -> Self {
+        Self {
+            key: data as u16,
+            score: (data >> 16) as u16 as i16,
+            depth: (data >> 32) as u8,
+            move_and_bound: (data >> 40) as u16,
+            age: (data >> 56) as u8
+        }
+    }
+}
+
+pub const TT_BUCKET_SIZE: usize = 8;
+const AGE_CYCLE: u8 = 64; // age is stored in 6 bits
+const SAME_KEY_DEPTH_MARGIN: u8 = 3; // a shallower entry of the same position still replaces one this much deeper
+
+// One cache line of entries, each packed in a single atomic u64 so reads and writes from
+// different threads are never torn
+#[derive(Default)]
+#[repr(align(64))]
+struct TTBucket {
+    entries: [AtomicU64; TT_BUCKET_SIZE]
+}
+
+// Lock-free transposition table shared by all search threads
+pub struct TT {
+    buckets: Vec<TTBucket>,
+    age: AtomicU8 // incremented every search
+}
+
+impl TT
+{
+    pub fn new(size_mb: usize) -> Self {
+        let num_buckets: usize = size_mb * 1024 * 1024 / mem::size_of::<TTBucket>();
+        Self {
+            buckets: (0..num_buckets).map(|_| TTBucket::default()).collect(),
+            age: AtomicU8::new(0)
+        }
+    }
+
+    // Number of entries
+    pub fn len(&self) -> usize {
+        self.buckets.len() * TT_BUCKET_SIZE
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.buckets.len() * mem::size_of::<TTBucket>()
+    }
+
+    pub fn clear(&self)
+    {
+        for bucket in self.buckets.iter() {
+            for entry in bucket.entries.iter() {
+                entry.store(0, Ordering::Relaxed);
+            }
+        }
+
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_age(&self) {
+        let age: u8 = self.age.load(Ordering::Relaxed);
+        self.age.store((age + 1) % AGE_CYCLE, Ordering::Relaxed);
+    }
+
+    // Fast range: maps the hash to [0, num_buckets) with a multiply and shift instead of a modulo
+    #[inline]
+    fn bucket(&self, zobrist_hash: u64) -> &TTBucket {
+        let index = ((zobrist_hash as u128 * self.buckets.len() as u128) >> 64) as usize;
+        &self.buckets[index]
+    }
+
+    pub fn probe(&self, zobrist_hash: u64) -> Option<TTEntry>
+    {
+        let key: u16 = zobrist_hash as u16;
+
+        for entry in self.bucket(zobrist_hash).entries.iter()
+        {
+            let tt_entry = TTEntry::unpack(entry.load(Ordering::Relaxed));
+
+            if tt_entry.key == key && tt_entry.get_bound() != Bound::None {
+                return Some(tt_entry);
+            }
+        }
+
+        None
+    }
+
+    // Permille of entries used in the current search, sampled from the first 1000 entries
+    pub fn hashfull(&self) -> u32
+    {
+        let age: u8 = self.age.load(Ordering::Relaxed);
+        let num_buckets: usize = (1000 / TT_BUCKET_SIZE).min(self.buckets.len());
+        let mut used: usize = 0;
+
+        for bucket in self.buckets.iter().take(num_buckets) {
+            for entry in bucket.entries.iter() {
+                let tt_entry = TTEntry::unpack(entry.load(Ordering::Relaxed));
+                used += (tt_entry.get_bound() != Bound::None && tt_entry.age == age) as usize;
+            }
+        }
+
+        (used * 1000 / (num_buckets * TT_BUCKET_SIZE).max(1)) as u32
+    }
+
+    // Stores in the entry with the same key if there is one, unless that entry is from this search, 
+    // much deeper and the new one isn't exact (e.g. qsearch entries don't replace deep ones).
+    // Otherwise replaces the entry with the lowest depth, where entries from previous searches count as shallower.
+    // Returns true if an entry of another position was overwritten.
+    pub fn store(&self, zobrist_hash: u64, mut tt_entry: TTEntry) -> bool
+    {
+        let key: u16 = zobrist_hash as u16;
+        let age: u8 = self.age.load(Ordering::Relaxed);
+        let bucket: &TTBucket = self.bucket(zobrist_hash);
+
+        let mut replace_idx: usize = 0;
+        let mut replace_value: i32 = i32::MAX;
+
+        for (i, entry) in bucket.entries.iter().enumerate()
+        {
+            let old_entry = TTEntry::unpack(entry.load(Ordering::Relaxed));
+
+            if old_entry.key == key && old_entry.get_bound() != Bound::None
+            {
+                if tt_entry.get_bound() != Bound::Exact 
+                && old_entry.age == age
+                && tt_entry.depth + SAME_KEY_DEPTH_MARGIN < old_entry.depth
+                {
+                    return false;
+                }
+
+                // Keep the old move if we don't have one
+                if tt_entry.get_move() == MOVE_NONE {
+                    tt_entry.set_move(old_entry.get_move());
+                }
+
+                bucket.entries[i].store(tt_entry.with_key_and_age(key, age).pack(), Ordering::Relaxed);
+                return false;
+            }
+
+            let relative_age: u8 = (AGE_CYCLE + age - old_entry.age) % AGE_CYCLE;
+            let value: i32 = if old_entry.get_bound() == Bound::None {
+                i32::MIN
+            } else {
+                old_entry.depth as i32 - 8 * relative_age as i32
+            };
+
+            if value < replace_value {
+                replace_idx = i;
+                replace_value = value;
+            }
+        }
+
+        bucket.entries[replace_idx].store(tt_entry.with_key_and_age(key, age).pack(), Ordering::Relaxed);
+        replace_value != i32::MIN
+    }
+}
+
+// TT usage of a search, for the 'ttstats' command
+#[derive(Clone, Copy, Default)]
+pub struct TTStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: [u64; 4], // [bound]
+    pub overwrites: u64, // stores that replaced an entry of another position
+    pub illegal_moves: u64, // hits with an illegal TT move, which means a key collision
+}
+
+impl TTStats
+{
+    pub fn add(&mut self, other: &TTStats)
+    {
+        self.probes += other.probes;
+        self.hits += other.hits;
+        self.overwrites += other.overwrites;
+        self.illegal_moves += other.illegal_moves;
+
+        for (stores, other_stores) in self.stores.iter_mut().zip(other.stores) {
+            *stores += other_stores;
+        }
+    }
+
+    pub fn print(&self)
+    {
+        let percentage = |count: u64, total: u64| count as f64 * 100.0 / total.max(1) as f64;
+        let total_stores: u64 = self.stores.iter().sum();
+
+        println!("TT probes: {}", self.probes);
+        println!("TT hits: {} ({:.1}%)", self.hits, percentage(self.hits, self.probes));
+        println!("TT stores: {} (exact {:.1}%, lower {:.1}%, upper {:.1}%)", 
+            total_stores,
+            percentage(self.stores[Bound::Exact as usize], total_stores),
+            percentage(self.stores[Bound::Lower as usize], total_stores),
+            percentage(self.stores[Bound::Upper as usize], total_stores));
+        println!("TT overwrites of other positions: {} ({:.1}% of stores)", 
+            self.overwrites, percentage(self.overwrites, total_stores));
+        println!("TT key collisions (hits with illegal TT move): {}", self.illegal_moves);
     }
 }
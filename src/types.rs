@@ -1,6 +1,5 @@
 pub const I32_MAX: i32 = 2147483647;
 pub const U64_MAX: u64 = 18446744073709551615;
-pub const I64_MAX: i64 = 9223372036854775807;
 
 pub const START_FEN: &str = "x5o/7/7/7/7/7/o5x x 0 1";
 pub const INFINITY: i32 = 32000;
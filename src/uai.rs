@@ -1,57 +1,134 @@
 use std::io;
+use std::thread;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::JoinHandle;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::types::*;
 use crate::ataxx_move::*;
 use crate::board::*;
 use crate::nn::*;
 use crate::perft::*;
 use crate::search::*;
+use crate::time_manager::*;
 use crate::bench::*;
 use crate::datagen::*;
+use crate::uai_command::*;
+use arrayvec::ArrayVec;
 
 pub fn uai_loop()
 {
-    let mut searcher: Searcher = Searcher::new(Board::new(START_FEN));
-    searcher.print_tt_size();
+    let searcher_mutex = Arc::new(Mutex::new(Searcher::new(Board::new(START_FEN))));
+    let stop = searcher_mutex.lock().unwrap().stop_flag();
+    let ponder = searcher_mutex.lock().unwrap().ponder_flag();
+    let mut search_thread: Option<JoinHandle<()>> = None;
+    let mut infinite_search: bool = false; // the search in progress, if any, is 'go infinite'
+    searcher_mutex.lock().unwrap().print_tt_size();
 
     loop
     {
         let mut input = String::new();
-        let _ = io::stdin().read_line(&mut input);
-        input = input.trim().to_string();
-        let input_split: Vec<&str> = input.split(' ').map(str::trim).collect();
 
-        match input_split[0] {
-            "quit" => {
+        // EOF: same as quit, since nothing can stop an infinite or ponder search anymore
+        if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            stop.store(true, Ordering::Relaxed);
+            wait_for_search(&mut search_thread, &stop, &ponder, &searcher_mutex);
+            break;
+        }
+
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        let command: UaiCommand = match parse_command(&input) {
+            Ok(command) => command,
+            Err(error) => {
+                println!("info string error {}", error);
+                continue;
+            }
+        };
+
+        // These commands must be answered while a search is running
+        match command {
+            UaiCommand::IsReady => { 
+                println!("readyok"); 
+                continue;
+            }
+            UaiCommand::PonderHit => {
+                ponder.store(false, Ordering::Relaxed);
+                continue;
+            }
+            UaiCommand::Stop => {
+                stop.store(true, Ordering::Relaxed);
+                wait_for_search(&mut search_thread, &stop, &ponder, &searcher_mutex);
+                continue;
+            }
+            UaiCommand::Quit => {
+                stop.store(true, Ordering::Relaxed);
+                wait_for_search(&mut search_thread, &stop, &ponder, &searcher_mutex);
                 break;
             }
-            "uai" => {
+            _ => { }
+        }
+
+        // Infinite and ponder searches only end on 'stop' (or 'ponderhit'), so waiting for them
+        // here would block reading that command
+        if search_thread.is_some() && (infinite_search || ponder.load(Ordering::Relaxed)) {
+            println!("info string error search in progress, send stop first");
+            continue;
+        }
+
+        // All other commands use the searcher, so wait for the search in progress to finish
+        wait_for_search(&mut search_thread, &stop, &ponder, &searcher_mutex);
+        let mut searcher = searcher_mutex.lock().unwrap();
+
+        match command {
+            UaiCommand::Uai => {
                 println!("id name Zataxx");
                 println!("id author zzzzz");
                 println!("option name Hash type spin default {} min 1 max 1024", TT_DEFAULT_MB);
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTIPV);
+                println!("option name EvalFile type string default {}", EMBEDDED_NET_NAME);
+                println!("option name Quiescence type check default true");
+                println!("option name Ponder type check default false");
+                println!("option name UAI_ShowWDL type check default false");
+                println!("option name UAI_LimitStrength type check default false");
+                println!("option name Skill Level type spin default {} min 0 max {}", 
+                    MAX_SKILL_LEVEL, MAX_SKILL_LEVEL);
+                println!("option name MoveOverhead type spin default {} min 0 max {}", 
+                    DEFAULT_MOVE_OVERHEAD, MAX_MOVE_OVERHEAD);
                 //list_params();
                 println!("uaiok");
             }
-            "setoption" => { 
-                setoption(input_split, &mut searcher);
-            }
-            "isready" => { 
-                println!("readyok"); 
+            UaiCommand::SetOption { name, value } => { 
+                if let Err(error) = setoption(&name, &value, &mut searcher) {
+                    println!("info string error {}", error);
+                }
             }
-            "uainewgame" => { 
+            UaiCommand::UaiNewGame => { 
                 uainewgame(&mut searcher);
             }
-            "position" => { 
-                position(input_split, &mut searcher);
+            UaiCommand::Position { fen, moves } => { 
+                if let Err(error) = position(fen.as_deref(), &moves, &mut searcher) {
+                    println!("info string error {}", error);
+                }
              }
-            "go" => { 
-                go(input_split, &mut searcher);
+            UaiCommand::Go(params) => { 
+                for ignored in params.ignored.iter() {
+                    println!("info string warning ignoring unsupported go parameter {}", ignored);
+                }
+
+                infinite_search = params.infinite;
+                drop(searcher);
+                search_thread = Some(go(params, Arc::clone(&searcher_mutex)));
              }
-             "d" | "display" | "print" | "show" => 
+             UaiCommand::Display => 
              {
                 uainewgame(&mut searcher);
 
-                let score = searcher.search(DEFAULT_MAX_DEPTH, I64_MAX, 0, 
-                                true, 100_000, 150_000, false).1 as i64;
+                let score = searcher.search(DEFAULT_MAX_DEPTH, TimeManager::infinite(), 
+                                100_000, 150_000, false).1 as i64;
 
                 uainewgame(&mut searcher);
 
@@ -80,8 +157,8 @@ pub fn uai_loop()
                         else {
                             searcher.board.remove_piece(piece_color, sq);
 
-                            let score_no_piece = searcher.search(DEFAULT_MAX_DEPTH, I64_MAX, 0, 
-                                                     true, 100_000, 150_000, false).1 as i64;
+                            let score_no_piece = searcher.search(DEFAULT_MAX_DEPTH, TimeManager::infinite(), 
+                                                     100_000, 150_000, false).1 as i64;
 
                             uainewgame(&mut searcher);
 
@@ -99,73 +176,166 @@ pub fn uai_loop()
                 println!();
                 println!("Fen: {}", searcher.board.fen());
                 println!("Zobrist hash: {}", searcher.board.zobrist_hash());
-                println!("Eval: {} ", evaluate(&searcher.board));
+                println!("Eval: {} ", evaluate(&mut searcher.board));
              }
-            "eval" | "evaluate" | "evaluation" => {
-                println!("eval {}", evaluate(&searcher.board));
+            UaiCommand::TTStats => {
+                searcher.print_tt_stats();
+            }
+            UaiCommand::Eval => {
+                println!("eval {}", evaluate(&mut searcher.board));
             }
-            "perft" => {  
-                let depth: u8 = input_split[1].parse::<u8>().unwrap();
+            UaiCommand::Perft(depth) => {  
                 perft_bench(&mut searcher.board, depth);
             }
-            "perftsplit" | "splitperft" => { 
-                let depth: u8 = input_split[1].parse::<u8>().unwrap();
+            UaiCommand::PerftSplit(depth) => { 
                 perft_split(&mut searcher.board, depth);
             }
-            "bench" => {
-                let depth: u8 = if input_split.len() == 2 { 
-                    input_split[1].parse::<u8>().unwrap() 
+            UaiCommand::Bench(depth) => {
+                bench(depth.unwrap_or(DEFAULT_BENCH_DEPTH), searcher.is_qsearch_enabled());
+            }
+            UaiCommand::MakeMove(mov) => {
+                if is_legal(&mut searcher.board, mov) {
+                    searcher.board.make_move(mov);
                 }
                 else {
-                    DEFAULT_BENCH_DEPTH
-                };
-
-                bench(depth);
-            }
-            "makemove" => {
-                searcher.board.make_move(AtaxxMove::from_uai(input_split[1]));
+                    println!("info string error illegal move {}", mov);
+                }
             }
-            "undomove" => {
-                searcher.board.undo_move();
+            UaiCommand::UndoMove => {
+                if searcher.board.last_move() == MOVE_NONE {
+                    println!("info string error no move to undo");
+                }
+                else {
+                    searcher.board.undo_move();
+                }
             }
-            "datagen_openings" => {
-                datagen_openings();
+            UaiCommand::DatagenOpenings => {
+                datagen_openings(&OpeningsConfig::default());
             }
-            "datagen" => {
-                datagen();
+            UaiCommand::Datagen => {
+                datagen(&DatagenConfig::default());
             }
-            _ => { }
+            UaiCommand::IsReady | UaiCommand::PonderHit | UaiCommand::Stop | UaiCommand::Quit => { }
         }
     }
 }
 
-pub fn setoption(tokens: Vec<&str>, searcher: &mut Searcher)
+// Once no search is running, a 'stop' has nothing left to stop and a ponder search stopped before 
+// its 'ponderhit' has nothing left to wait for, so both flags are cleared.
+// If the search panicked, the searcher's board may be left mid-search, so it's reset to the start position.
+pub fn wait_for_search(search_thread: &mut Option<JoinHandle<()>>, stop: &AtomicBool, ponder: &AtomicBool, 
+    searcher_mutex: &Mutex<Searcher>)
 {
-    let option_name: &str = tokens[2];
-    let option_value: &str = tokens[4];
-    
-    if option_name == "hash" || option_name == "Hash" {
-        searcher.resize_tt(option_value.parse::<usize>().unwrap());
-        searcher.print_tt_size();
-        return; 
+    if let Some(handle) = search_thread.take() 
+    {
+        if handle.join().is_err() 
+        {
+            println!("info string error search failed, position reset to startpos");
+            searcher_mutex.clear_poison();
+            let mut searcher = searcher_mutex.lock().unwrap();
+            searcher.board = Board::new(START_FEN);
+            uainewgame(&mut searcher);
+        }
     }
 
-    if let Err(_) = option_value.parse::<f64>() {
-        println!("Unknown option {} or value {}", option_name, option_value);
-        return;
-    }
+    stop.store(false, Ordering::Relaxed);
+    ponder.store(false, Ordering::Relaxed);
+}
 
-    let new_param_value: f64 = option_value.parse::<f64>().unwrap();
+// name is lowercase, so options are case insensitive
+pub fn setoption(name: &str, value: &str, searcher: &mut Searcher) -> Result<(), String>
+{
+    let parse_int = |min: u64, max: u64| -> Result<u64, String> {
+        let int: u64 = value.parse::<u64>().map_err(|_| format!("invalid value {} for {}", value, name))?;
+        Ok(int.clamp(min, max))
+    };
 
-    match set_param(option_name, new_param_value) 
-    {
-        Ok(updated_value_as_str) => { 
-            if option_name == stringify!(lmr_base) || option_name == stringify!(lmr_multiplier) {
+    let parse_bool = || -> Result<bool, String> {
+        match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(format!("invalid value {} for {}, expected true or false", value, name))
+        }
+    };
+
+    match name {
+        "hash" => {
+            searcher.resize_tt(parse_int(1, 1024)? as usize);
+            searcher.print_tt_size();
+        }
+        "threads" => {
+            let num_threads: usize = parse_int(1, MAX_THREADS as u64)? as usize;
+            searcher.set_threads(num_threads);
+            println!("Threads set to {}", num_threads);
+        }
+        "evalfile" => {
+            set_eval_file(value);
+            searcher.board.reset_accumulators();
+        }
+        "moveoverhead" => {
+            let move_overhead: u64 = parse_int(0, MAX_MOVE_OVERHEAD)?;
+            searcher.set_move_overhead(move_overhead);
+            println!("MoveOverhead set to {}", move_overhead);
+        }
+        "uai_limitstrength" => {
+            let enabled: bool = parse_bool()?;
+            searcher.set_limit_strength(enabled);
+            println!("UAI_LimitStrength set to {}", enabled);
+        }
+        "skill level" => {
+            let skill_level: u8 = parse_int(0, MAX_SKILL_LEVEL as u64)? as u8;
+            searcher.set_skill_level(skill_level);
+            println!("Skill Level set to {}", skill_level);
+        }
+        "uai_showwdl" => {
+            let enabled: bool = parse_bool()?;
+            searcher.set_show_wdl(enabled);
+            println!("UAI_ShowWDL set to {}", enabled);
+        }
+        "ponder" => {
+            let enabled: bool = parse_bool()?;
+            searcher.set_ponder(enabled);
+            println!("Ponder set to {}", enabled);
+        }
+        "quiescence" => {
+            let enabled: bool = parse_bool()?;
+            searcher.set_qsearch(enabled);
+            println!("Quiescence set to {}", enabled);
+        }
+        "multipv" => {
+            let multipv: usize = parse_int(1, MAX_MULTIPV as u64)? as usize;
+            searcher.set_multipv(multipv);
+            println!("MultiPV set to {}", multipv);
+        }
+        _ => {
+            // Search tunables
+            let new_param_value: f64 = value.parse::<f64>()
+                .map_err(|_| format!("unknown option {} or invalid value {}", name, value))?;
+
+            let updated_value_as_str: String = set_param(name, new_param_value)
+                .map_err(|_| format!("unknown option {}", name))?;
+
+            if name == stringify!(lmr_base) || name == stringify!(lmr_multiplier) {
                 searcher.init_lmr_table();
             }
-            println!("{} set to {}", option_name, updated_value_as_str);
+            println!("{} set to {}", name, updated_value_as_str);
         }
-        Err(_msg) => println!("Unknown option {}", option_name)
+    }
+
+    Ok(())
+}
+
+pub fn set_eval_file(path: &str)
+{
+    if path == EMBEDDED_NET_NAME || path.is_empty() {
+        use_embedded_net();
+        println!("Using embedded net");
+        return;
+    }
+
+    match load_net(path) {
+        Ok(()) => println!("Loaded net {}", path),
+        Err(error) => println!("{}. Using embedded net instead.", error)
     }
 }
 
@@ -176,91 +346,110 @@ pub fn uainewgame(searcher: &mut Searcher)
     searcher.clear_history();
 }
 
-pub fn position(tokens: Vec<&str>, searcher: &mut Searcher)
+fn is_legal(board: &mut Board, mov: AtaxxMove) -> bool
 {
-    // apply fen
-    if tokens[1] == "startpos" {
-       searcher.board = Board::new(START_FEN);
-    }
-    else if tokens[1] == "fen"
-    {
-        let mut fen = String::new();
-        for token in tokens.iter().skip(2) {
-            if token == &"moves" {
-                break;
-            }
-            fen += token;
-            fen.push(' ');
-        }
-        fen.pop(); // remove last whitespace
-        searcher.board = Board::new(&fen);
+    if board.game_state().0 != GameState::Ongoing {
+        return false;
     }
 
-    // apply moves if any
-    for (i, token) in tokens.iter().skip(2).enumerate()
+    let mut moves = ArrayVec::<AtaxxMove, 256>::new();
+    board.moves(&mut moves);
+    moves.contains(&mov)
+}
+
+// The position is only changed if the fen and all moves are valid
+pub fn position(fen: Option<&str>, moves: &[AtaxxMove], searcher: &mut Searcher) -> Result<(), String>
+{
+    let mut board: Board = Board::from_fen(fen.unwrap_or(START_FEN))
+        .map_err(|error| format!("invalid fen {}: {}", fen.unwrap_or(START_FEN), error))?;
+
+    for mov in moves.iter() 
     {
-        if token == &"moves" {
-            for j in (i+3)..tokens.len() {
-                searcher.board.make_move(AtaxxMove::from_uai(tokens[j as usize]));
-            }
-            break;
+        if !is_legal(&mut board, *mov) {
+            return Err(format!("illegal move {} in position {}", mov, board.fen()));
         }
+
+        board.make_move(*mov);
     }
+
+    searcher.board = board;
+    Ok(())
 }
 
-pub fn go(tokens: Vec<&str>, searcher: &mut Searcher)
+// Starts the search in a new thread, which prints bestmove when done
+pub fn go(params: GoParams, searcher_mutex: Arc<Mutex<Searcher>>) -> JoinHandle<()>
 {
-    let mut milliseconds: i64 = I64_MAX;
-    let mut increment_ms: u64 = 0;
-    let mut is_move_time = false;
-    let mut depth = DEFAULT_MAX_DEPTH;
-    let mut nodes = U64_MAX;
-
-    let mut is_wtime_btime: bool = true;
-    for i in (1..tokens.len()).step_by(2) {
-        if tokens[i] == "rtime" {
-            is_wtime_btime = false;
-            break;
-        }
-    }
+    let mut searcher = searcher_mutex.lock().unwrap();
+    let stm: Color = searcher.board.side_to_move();
+    let depth: u8 = params.depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    let nodes: u64 = params.nodes.unwrap_or(U64_MAX);
 
-    for i in (1..tokens.len()).step_by(2) 
-    {
-        let stm: Color = searcher.board.side_to_move();
+    // Illegal searchmoves are ignored
+    let mut legal_moves = ArrayVec::<AtaxxMove, 256>::new();
+    searcher.board.moves(&mut legal_moves);
 
-        if (tokens[i] == "rtime" && stm == Color::Red)
-        || (tokens[i] == "wtime" && stm == Color::Blue)
-        || (tokens[i] == "btime" 
-        && ((is_wtime_btime && stm == Color::Red)
-        || (!is_wtime_btime && stm == Color::Blue)))
-        {
-            milliseconds = tokens[i+1].parse().unwrap();
-        }
-        else if (tokens[i] == "rinc" && stm == Color::Red)
-        || (tokens[i] == "winc" && stm == Color::Blue)
-        || (tokens[i] == "binc" 
-        && ((is_wtime_btime && stm == Color::Red)
-        || (!is_wtime_btime && stm == Color::Blue)))
-        {
-            increment_ms = tokens[i+1].parse().unwrap();
-        }
-        else if tokens[i] == "movetime" {
-            is_move_time = true;
-            milliseconds = tokens[i+1].parse().unwrap();
-        }
-        else if tokens[i] == "depth" {
-            depth = tokens[i+1].parse::<i64>().unwrap().clamp(0, 255) as u8;
+    let mut search_moves = ArrayVec::<AtaxxMove, 256>::new();
+    for mov in params.search_moves.iter() 
+    {
+        if !legal_moves.contains(mov) {
+            println!("info string error illegal searchmoves move {}", mov);
         }
-        else if tokens[i] == "nodes" {
-            nodes = tokens[i+1].parse().unwrap();
+        else if !search_moves.contains(mov) {
+            search_moves.push(*mov);
         }
     }
 
-    if is_move_time { increment_ms = 0; }
+    // Sent if the search panics, so the GUI still gets a bestmove
+    let fallback_move: AtaxxMove = search_moves.first().or(legal_moves.first()).copied().unwrap_or(MOVE_PASS);
+
+    searcher.set_search_moves(search_moves);
+    searcher.set_infinite(params.infinite);
+
+    let time_manager: TimeManager = if let Some(move_time) = params.move_time {
+        TimeManager::move_time(move_time, searcher.move_overhead())
+    }
+    else if let Some(milliseconds) = params.time(stm) {
+        TimeManager::new(milliseconds, params.inc(stm).unwrap_or(0), params.moves_to_go, searcher.move_overhead())
+    }
+    else {
+        TimeManager::infinite()
+    };
+
+    searcher.stop_flag().store(false, Ordering::Relaxed);
+    searcher.ponder_flag().store(params.ponder, Ordering::Relaxed);
+    drop(searcher);
+
+    thread::Builder::new()
+        .stack_size(SEARCH_THREAD_STACK_SIZE)
+        .spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| search_and_print(&searcher_mutex, depth, time_manager, nodes)));
+
+            // wait_for_search() resets the searcher when the next command arrives
+            if let Err(payload) = result {
+                println!("bestmove {}", fallback_move);
+                panic::resume_unwind(payload);
+            }
+        })
+        .unwrap()
+}
+
+fn search_and_print(searcher_mutex: &Mutex<Searcher>, depth: u8, time_manager: TimeManager, nodes: u64)
+{
+    let mut searcher = searcher_mutex.lock().unwrap();
 
-    let best_move = searcher.search(depth, milliseconds, increment_ms, 
-                        is_move_time, U64_MAX, nodes, true).0;
+    let best_move = searcher.search(depth, time_manager, U64_MAX, nodes, true).0;
 
     assert!(best_move != MOVE_NONE);
-    println!("bestmove {}", best_move);
+
+    let ponder_move = if searcher.is_ponder_enabled() {
+        searcher.ponder_move(best_move)
+    } else {
+        MOVE_NONE
+    };
+
+    if ponder_move == MOVE_NONE {
+        println!("bestmove {}", best_move);
+    } else {
+        println!("bestmove {} ponder {}", best_move, ponder_move);
+    }
 }
\ No newline at end of file
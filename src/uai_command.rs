@@ -0,0 +1,215 @@
+use std::str::FromStr;
+use crate::types::*;
+use crate::ataxx_move::*;
+
+// Parameters of 'go', None if not given
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct GoParams {
+    pub red_time: Option<i64>,
+    pub blue_time: Option<i64>,
+    pub red_inc: Option<u64>,
+    pub blue_inc: Option<u64>,
+    pub moves_to_go: Option<u64>,
+    pub move_time: Option<u64>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub ponder: bool,
+    pub infinite: bool,
+    pub search_moves: Vec<AtaxxMove>,
+    pub ignored: Vec<String>, // unknown parameters with their values, e.g. "mate 3"
+}
+
+impl GoParams
+{
+    pub fn time(&self, color: Color) -> Option<i64> {
+        if color == Color::Red { self.red_time } else { self.blue_time }
+    }
+
+    pub fn inc(&self, color: Color) -> Option<u64> {
+        if color == Color::Red { self.red_inc } else { self.blue_inc }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum UaiCommand {
+    Uai,
+    IsReady,
+    UaiNewGame,
+    SetOption { name: String, value: String }, // name is lowercase
+    Position { fen: Option<String>, moves: Vec<AtaxxMove> }, // fen is None for startpos
+    Go(GoParams),
+    PonderHit,
+    Stop,
+    Quit,
+    Display,
+    Eval,
+    TTStats,
+    Perft(u8),
+    PerftSplit(u8),
+    Bench(Option<u8>),
+    MakeMove(AtaxxMove),
+    UndoMove,
+    DatagenOpenings,
+    Datagen,
+}
+
+const GO_KEYWORDS: [&str; 13] = [
+    "wtime", "btime", "rtime", "winc", "binc", "rinc", "movestogo",
+    "movetime", "depth", "nodes", "ponder", "infinite", "searchmoves"
+];
+
+// Parses a line from the GUI, or returns a description of what's wrong with it
+pub fn parse_command(line: &str) -> Result<UaiCommand, String>
+{
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+
+    let command = match tokens.first() {
+        Some(command) => *command,
+        None => return Err("empty command".to_string())
+    };
+
+    match command {
+        "uai" => Ok(UaiCommand::Uai),
+        "isready" => Ok(UaiCommand::IsReady),
+        "uainewgame" => Ok(UaiCommand::UaiNewGame),
+        "setoption" => parse_setoption(&tokens),
+        "position" => parse_position(&tokens),
+        "go" => parse_go(&tokens),
+        "ponderhit" => Ok(UaiCommand::PonderHit),
+        "stop" => Ok(UaiCommand::Stop),
+        "quit" => Ok(UaiCommand::Quit),
+        "d" | "display" | "print" | "show" => Ok(UaiCommand::Display),
+        "eval" | "evaluate" | "evaluation" => Ok(UaiCommand::Eval),
+        "ttstats" => Ok(UaiCommand::TTStats),
+        "perft" => Ok(UaiCommand::Perft(parse_value(&tokens, 1, "perft depth")?)),
+        "perftsplit" | "splitperft" => Ok(UaiCommand::PerftSplit(parse_value(&tokens, 1, "perftsplit depth")?)),
+        "bench" => {
+            let depth: Option<u8> = if tokens.len() > 1 { Some(parse_value::<u8>(&tokens, 1, "bench depth")?.max(1)) } else { None };
+            Ok(UaiCommand::Bench(depth))
+        }
+        "makemove" => Ok(UaiCommand::MakeMove(parse_move(tokens.get(1).copied())?)),
+        "undomove" => Ok(UaiCommand::UndoMove),
+        "datagen_openings" => Ok(UaiCommand::DatagenOpenings),
+        "datagen" => Ok(UaiCommand::Datagen),
+        _ => Err(format!("unknown command {}", command))
+    }
+}
+
+fn parse_value<T: FromStr>(tokens: &[&str], i: usize, name: &str) -> Result<T, String>
+{
+    let token: &str = tokens.get(i).ok_or(format!("missing {}", name))?;
+    token.parse::<T>().map_err(|_| format!("invalid {} {}", name, token))
+}
+
+fn parse_move(token: Option<&str>) -> Result<AtaxxMove, String>
+{
+    let token: &str = token.ok_or("missing move")?;
+    AtaxxMove::try_from_uai(token).ok_or(format!("invalid move {}", token))
+}
+
+// setoption name <name> [value <value>], both may contain spaces
+fn parse_setoption(tokens: &[&str]) -> Result<UaiCommand, String>
+{
+    if tokens.get(1) != Some(&"name") {
+        return Err("expected setoption name <name> value <value>".to_string());
+    }
+
+    let value_idx: usize = tokens.iter().position(|token| *token == "value").unwrap_or(tokens.len());
+    let name: String = tokens[2.min(value_idx)..value_idx].join(" ").to_lowercase();
+
+    if name.is_empty() {
+        return Err("missing option name".to_string());
+    }
+
+    let value: String = tokens[(value_idx + 1).min(tokens.len())..].join(" ");
+    Ok(UaiCommand::SetOption { name, value })
+}
+
+// position startpos|fen <fen> [moves <move> ...]
+fn parse_position(tokens: &[&str]) -> Result<UaiCommand, String>
+{
+    let moves_idx: usize = tokens.iter().position(|token| *token == "moves").unwrap_or(tokens.len());
+
+    let fen: Option<String> = match tokens.get(1).copied() {
+        Some("startpos") if moves_idx == 2 => None,
+        Some("startpos") => return Err(format!("unexpected {} after startpos", tokens[2])),
+        Some("fen") if moves_idx > 2 => Some(tokens[2..moves_idx].join(" ")),
+        Some("fen") => return Err("missing fen".to_string()),
+        Some(token) => return Err(format!("expected startpos or fen, got {}", token)),
+        None => return Err("expected startpos or fen".to_string())
+    };
+
+    let moves: Vec<AtaxxMove> = tokens.iter()
+        .skip(moves_idx + 1)
+        .map(|token| parse_move(Some(token)))
+        .collect::<Result<_, _>>()?;
+
+    Ok(UaiCommand::Position { fen, moves })
+}
+
+fn parse_go(tokens: &[&str]) -> Result<UaiCommand, String>
+{
+    let mut params = GoParams::default();
+    let (mut wtime, mut btime, mut rtime) = (None, None, None);
+    let (mut winc, mut binc, mut rinc) = (None, None, None);
+
+    let mut i: usize = 1;
+    while i < tokens.len()
+    {
+        let token: &str = tokens[i];
+
+        match token {
+            "wtime" => wtime = Some(parse_value(tokens, i + 1, token)?),
+            "btime" => btime = Some(parse_value(tokens, i + 1, token)?),
+            "rtime" => rtime = Some(parse_value(tokens, i + 1, token)?),
+            "winc" => winc = Some(parse_value(tokens, i + 1, token)?),
+            "binc" => binc = Some(parse_value(tokens, i + 1, token)?),
+            "rinc" => rinc = Some(parse_value(tokens, i + 1, token)?),
+            "movestogo" => params.moves_to_go = Some(parse_value(tokens, i + 1, token)?),
+            "movetime" => params.move_time = Some(parse_value(tokens, i + 1, token)?),
+            "depth" => {
+                // The search needs at least one iteration to have a move
+                let depth: i64 = parse_value(tokens, i + 1, token)?;
+                params.depth = Some(depth.clamp(1, 255) as u8);
+            }
+            "nodes" => params.nodes = Some(parse_value(tokens, i + 1, token)?),
+            "ponder" => params.ponder = true,
+            "infinite" => params.infinite = true,
+            "searchmoves" => {
+                // Moves until the next keyword
+                while i + 1 < tokens.len() && !GO_KEYWORDS.contains(&tokens[i + 1]) {
+                    params.search_moves.push(parse_move(Some(tokens[i + 1]))?);
+                    i += 1;
+                }
+            }
+            _ => {
+                // Values until the next keyword are ignored too
+                let mut ignored: String = token.to_string();
+                while i + 1 < tokens.len() && !GO_KEYWORDS.contains(&tokens[i + 1]) {
+                    ignored = format!("{} {}", ignored, tokens[i + 1]);
+                    i += 1;
+                }
+                params.ignored.push(ignored);
+            }
+        }
+
+        // Skip the value
+        if GO_KEYWORDS.contains(&token) && !matches!(token, "ponder" | "infinite" | "searchmoves") {
+            i += 1;
+        }
+
+        i += 1;
+    }
+
+    // Red's time is rtime, or btime with wtime/btime
+    if rtime.is_some() || rinc.is_some() {
+        (params.red_time, params.blue_time) = (rtime, btime);
+        (params.red_inc, params.blue_inc) = (rinc, binc);
+    }
+    else {
+        (params.red_time, params.blue_time) = (btime, wtime);
+        (params.red_inc, params.blue_inc) = (binc, winc);
+    }
+
+    Ok(UaiCommand::Go(params))
+}
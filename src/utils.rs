@@ -1,6 +1,7 @@
 use std::time::Instant;
 use crate::types::*;
 use crate::ataxx_move::*;
+use crate::nn::SCALE;
 use arrayvec::ArrayVec;
 
 /*
@@ -81,6 +82,38 @@ pub fn milliseconds_elapsed(start_time: Instant) -> u64 {
     now.duration_since(start_time).as_millis() as u64
 }
 
+// Score part of an info line: 'mate N' for game results found by the search, 
+// N being the moves until the game ends (negative if we lose), otherwise 'cp N'
+pub fn score_to_string(score: i32) -> String
+{
+    if score.abs() < MIN_WIN_SCORE {
+        return format!("cp {}", score);
+    }
+
+    let moves: i32 = (INFINITY - score.abs() + 1) / 2;
+    format!("mate {}", if score > 0 { moves.max(1) } else { -moves.max(1) })
+}
+
+// Score offset at which a draw is as likely as a win.
+// Placeholder, not fitted on game results yet
+pub const WDL_DRAW_MARGIN: f64 = 60.0;
+
+// Win, draw and loss probabilities in permille: wins and losses are logistic in the score, 
+// shifted by WDL_DRAW_MARGIN. The net's SCALE is reused as the logistic scale, which is a guess, 
+// so the numbers are rough until margin and scale are fitted on datagen results
+pub fn wdl_model(score: i32) -> (u32, u32, u32)
+{
+    if score.abs() >= MIN_WIN_SCORE {
+        return if score > 0 { (1000, 0, 0) } else { (0, 0, 1000) };
+    }
+
+    let logistic = |x: f64| 1.0 / (1.0 + (-x / SCALE as f64).exp());
+    let win: u32 = (logistic(score as f64 - WDL_DRAW_MARGIN) * 1000.0).round() as u32;
+    let loss: u32 = (logistic(-score as f64 - WDL_DRAW_MARGIN) * 1000.0).round() as u32;
+
+    (win, 1000 - win - loss, loss)
+}
+
 pub fn incremental_sort(
     moves: &mut ArrayVec<AtaxxMove, 256>, 
     moves_scores: &mut ArrayVec<i32, 256>, 